@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::credentials::CredentialStore;
+use crate::github::{self, GitHub};
+use crate::gitlab::{self, GitLab};
+use crate::AppState;
+
+enum Client {
+    GitHub(GitHub),
+    GitLab(GitLab),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PrAction {
+    Approve,
+    Merge,
+    Rerun,
+    Close,
+    Ready,
+}
+
+impl PrAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Approve => "APPR",
+            Self::Merge => "MRG",
+            Self::Rerun => "RUN",
+            Self::Close => "CLS",
+            Self::Ready => "RDY",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ActionRequest {
+    pub action: PrAction,
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+    pub provider_id: String,
+    pub account: String,
+}
+
+pub async fn run(
+    data: Arc<Mutex<AppState>>,
+    mut requests: tokio::sync::mpsc::UnboundedReceiver<ActionRequest>,
+) {
+    let config = data.lock().unwrap().config.clone();
+    let store = CredentialStore::open().await;
+
+    let mut clients = HashMap::new();
+    for account in config.github_accounts {
+        if let Ok(gh) = GitHub::connect(account, &store).await {
+            clients.insert(gh.account().name.clone(), Client::GitHub(gh));
+        }
+    }
+    for account in config.gitlab_accounts {
+        if let Ok(gl) = GitLab::connect(account, &store).await {
+            clients.insert(gl.account().name.clone(), Client::GitLab(gl));
+        }
+    }
+
+    while let Some(req) = requests.recv().await {
+        let result = match clients.get(&req.account) {
+            Some(Client::GitHub(gh)) => github::execute(gh, &req).await,
+            Some(Client::GitLab(gl)) => gitlab::execute(gl, &req).await,
+            None => Err(format!("unknown account {}", req.account)),
+        };
+        let mut state = data.lock().unwrap();
+        let result = match result {
+            Ok(msg) => {
+                // Pick up the new PR state now rather than on the next poll
+                state.refresh_all();
+                msg
+            }
+            Err(e) => format!("FAILED: {e}"),
+        };
+        state.show_toast(format!(
+            "{} {}#{}: {}",
+            req.action.label(),
+            req.repo,
+            req.number,
+            result
+        ));
+    }
+}
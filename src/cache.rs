@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{config, AppState, CalendarEvent, Notification, PageState, PullRequest};
+
+// Last-known data and page, so the console is useful right after a restart or while offline
+#[derive(Deserialize)]
+struct Cache {
+    saved_at: i64,
+    page: PageState,
+    calendar: Option<CalendarEvent>,
+    notifications: Vec<Notification>,
+    open_prs: Vec<PullRequest>,
+    draft_prs: Vec<PullRequest>,
+    closed_prs: Vec<PullRequest>,
+    review_prs: Vec<PullRequest>,
+}
+
+#[derive(Serialize)]
+struct CacheRef<'a> {
+    saved_at: i64,
+    page: &'a PageState,
+    calendar: &'a Option<CalendarEvent>,
+    notifications: &'a [Notification],
+    open_prs: &'a [PullRequest],
+    draft_prs: &'a [PullRequest],
+    closed_prs: &'a [PullRequest],
+    review_prs: &'a [PullRequest],
+}
+
+fn path() -> std::path::PathBuf {
+    config::home_file(".console_gui_cache.json")
+}
+
+impl AppState {
+    pub fn load_cache(&mut self) {
+        if !path().exists() {
+            return;
+        }
+        if let Err(e) = self.load_cache_from(&path()) {
+            eprintln!("ignoring invalid cache: {e}");
+        }
+    }
+
+    // Also used to load fixtures, which are written in the cache format
+    pub fn load_cache_from(&mut self, path: &std::path::Path) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+        let cache: Cache =
+            serde_json::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        self.page = cache.page;
+        self.calendar = cache.calendar;
+        self.notifications = cache.notifications;
+        self.open_prs = cache.open_prs;
+        self.draft_prs = cache.draft_prs;
+        self.closed_prs = cache.closed_prs;
+        self.review_prs = cache.review_prs;
+        self.cached_at = Some(cache.saved_at);
+        Ok(())
+    }
+
+    pub fn save_cache(&mut self) {
+        if !self.cache_dirty {
+            return;
+        }
+        self.cache_dirty = false;
+
+        let cache = CacheRef {
+            saved_at: self.clock.timestamp(),
+            page: &self.page,
+            calendar: &self.calendar,
+            notifications: &self.notifications,
+            open_prs: &self.open_prs,
+            draft_prs: &self.draft_prs,
+            closed_prs: &self.closed_prs,
+            review_prs: &self.review_prs,
+        };
+        let contents = match serde_json::to_string(&cache) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("couldn't serialize cache: {:?}", e);
+                return;
+            }
+        };
+
+        // Write then rename, so a crash mid-write never leaves a truncated cache
+        let path = path();
+        let tmp = path.with_extension("json.tmp");
+        if let Err(e) = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, &path)) {
+            eprintln!("couldn't write cache {}: {:?}", path.display(), e);
+        }
+    }
+}
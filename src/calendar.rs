@@ -1,20 +1,63 @@
 use google_calendar3::CalendarHub;
 
+use crate::clock::SharedClock;
+use crate::config;
+use crate::credentials::{self, CredentialStore};
+use crate::scheduler::{DataSource, SourceKind};
 pub use crate::{AppState, CalendarEvent};
-use std::sync::{Arc, Mutex};
+
+const TOKENS_KEY: &str = "google-oauth-tokens";
+
+// Where tokens were kept in plaintext before the credential store existed
+fn legacy_tokens() -> std::path::PathBuf {
+    config::home_file(".console_gui_auth.json")
+}
 
 struct CalendarAPI {
     hub: CalendarHub,
+    store: CredentialStore,
+    token_cache: std::path::PathBuf,
+    saved_tokens: Option<String>,
 }
 
-pub async fn run(data: Arc<Mutex<AppState>>) {
-    let search_start = chrono::prelude::Local::now() - chrono::Duration::hours(2);
-    let search_end = search_start + chrono::Duration::days(2);
+pub struct Calendar {
+    api: Option<CalendarAPI>,
+    clock: SharedClock,
+}
+
+impl Calendar {
+    pub fn new(clock: SharedClock) -> Self {
+        Self { api: None, clock }
+    }
+}
+
+#[async_trait::async_trait]
+impl DataSource for Calendar {
+    type Output = Option<CalendarEvent>;
+
+    fn name(&self) -> String {
+        "calendar".to_string()
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Calendar
+    }
 
-    let cal = CalendarAPI::new().await;
+    async fn fetch(&mut self) -> Result<Self::Output, String> {
+        // Authentication may need the browser, so it happens on first fetch
+        if self.api.is_none() {
+            self.api = Some(CalendarAPI::new(false).await?);
+        }
+        let cal = self.api.as_mut().unwrap();
+
+        let now = self.clock.now();
+        let search_start = now - chrono::Duration::hours(2);
+        let search_end = search_start + chrono::Duration::days(2);
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
-    loop {
         let (_, events) = cal
             .hub
             .events()
@@ -24,140 +67,321 @@ pub async fn run(data: Arc<Mutex<AppState>>) {
             .time_max(&search_end.to_rfc3339())
             .doit()
             .await
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+        cal.save_tokens().await;
 
-        let mut output = None;
+        Ok(select_event(events.items.unwrap_or_default(), now))
+    }
 
-        let mut best_score = f32::NEG_INFINITY;
-        let mut best_start: Option<chrono::DateTime<_>> = None;
+    fn merge(&self, output: Self::Output, state: &mut AppState) {
+        state.calendar = output;
+    }
+}
 
-        for event in events.items.unwrap() {
-            let title = &event.summary.as_deref().unwrap_or("");
+// Pick the event to show: the next one to start, preferring the more important
+// one when two start together
+fn select_event(
+    events: Vec<google_calendar3::api::Event>,
+    now: chrono::DateTime<chrono::FixedOffset>,
+) -> Option<CalendarEvent> {
+    let mut output = None;
 
-            // Skip invalid events
-            if event.start.is_none()
-                || event.start.as_ref().unwrap().date_time.is_none()
-                || event.status.as_deref().unwrap_or("") == "cancelled"
-            {
-                continue;
-            }
-            let now = chrono::prelude::Local::now();
-            let start = chrono::DateTime::parse_from_rfc3339(
-                event.start.unwrap().date_time.as_ref().unwrap(),
-            )
-            .unwrap();
-
-            let end = chrono::DateTime::parse_from_rfc3339(
-                event.end.unwrap().date_time.as_ref().unwrap(),
-            )
-            .unwrap();
-
-            // If the current meeting is >75% over, don't show it
-            if now > end - ((end - start) * 3) / 4 {
-                continue;
-            }
+    let mut best_score = f32::NEG_INFINITY;
+    let mut best_start: Option<chrono::DateTime<_>> = None;
 
-            // If the event is not today, don't show it
-            if now.date() != start.date() {
-                //continue;
-            }
+    'events: for event in events {
+        let title = &event.summary.as_deref().unwrap_or("");
 
-            // Score the event. Show the most important upcoming event if there are two
-            let mut score = 0.0;
-
-            // Shorter meetings should be prioritized above longer ones
-            score -= (end - start).num_minutes() as f32 / 30.0;
-
-            if let Some(attendees) = event.attendees {
-                for attendee in attendees {
-                    if attendee.self_.is_some() {
-                        if let Some(status) = attendee.response_status {
-                            if status == "accepted" {
-                                score += 5.0;
-                            } else if status == "declined" {
-                                continue;
-                            } else if status == "needsAction" || status == "tentative" {
-                                score -= 10.0;
-                            }
+        // Skip cancelled events, all-day ones and any with times that don't parse
+        if event.status.as_deref().unwrap_or("") == "cancelled" {
+            continue;
+        }
+        let time = |time: &Option<google_calendar3::api::EventDateTime>| {
+            chrono::DateTime::parse_from_rfc3339(time.as_ref()?.date_time.as_ref()?).ok()
+        };
+        let (start, end) = match (time(&event.start), time(&event.end)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+
+        // If the current meeting is >75% over, don't show it
+        if now > start + ((end - start) * 3) / 4 {
+            continue;
+        }
+
+        // If the event is not today, don't show it
+        if now.date() != start.date() {
+            //continue;
+        }
+
+        // Score the event. Show the most important upcoming event if there are two
+        let mut score = 0.0;
+
+        // Shorter meetings should be prioritized above longer ones
+        score -= (end - start).num_minutes() as f32 / 30.0;
+
+        if let Some(attendees) = event.attendees {
+            for attendee in attendees {
+                if attendee.self_.is_some() {
+                    if let Some(status) = attendee.response_status {
+                        if status == "accepted" {
+                            score += 5.0;
+                        } else if status == "declined" {
+                            continue 'events;
+                        } else if status == "needsAction" || status == "tentative" {
+                            score -= 10.0;
                         }
                     }
                 }
             }
+        }
 
-            if best_start.is_some() && best_start.unwrap().timestamp() < start.timestamp() {
-                continue;
-            }
+        if best_start.is_some() && best_start.unwrap().timestamp() < start.timestamp() {
+            continue;
+        }
 
-            // Two events starting at the same time, but one is better
-            if best_start.is_some()
-                && best_start.unwrap().timestamp() == start.timestamp()
-                && best_score > score
-            {
-                continue;
-            }
+        // Two events starting at the same time, but one is better
+        if best_start.is_some()
+            && best_start.unwrap().timestamp() == start.timestamp()
+            && best_score > score
+        {
+            continue;
+        }
 
-            best_start = Some(start);
-            best_score = score;
-
-            let zoom_url = event
-                .conference_data
-                .as_ref()
-                .and_then(|d| d.entry_points.as_ref())
-                .and_then(|e| {
-                    if !e.is_empty() {
-                        e[0].label.clone()
-                    } else {
-                        None
-                    }
-                })
-                .and_then(|u| {
-                    // Transform in to an xdg-open compatible link
-                    // NOTE: xdg-open compatible link looks like this:
-                    // zoomus://zoom.us/join?action=join&confno=99917074685&pwd=RWprdkxOOEpUUU84ejRVZ09td1NPUT09
-                    let re = regex::Regex::new("/j/(.*?)\\?pwd=(.*?)$").unwrap();
-                    if let Some(cap) = re.captures_iter(&u).next() {
-                        return Some(format!(
-                            "zoomus://zoom.us/join?action=join&confno={}&pwd={}",
-                            &cap[1], &cap[2]
-                        ));
-                    }
-                    None
-                });
+        best_start = Some(start);
+        best_score = score;
 
-            output = Some(CalendarEvent {
-                title: event.summary.unwrap_or_default(),
-                time: format!("{} - {}", start.format("%l:%M%P"), end.format("%l:%M%P")),
-                start: start.timestamp(),
-                zoom_url,
+        let zoom_url = event
+            .conference_data
+            .as_ref()
+            .and_then(|d| d.entry_points.as_ref())
+            .and_then(|e| {
+                if !e.is_empty() {
+                    e[0].label.clone()
+                } else {
+                    None
+                }
+            })
+            .and_then(|u| {
+                // Transform in to an xdg-open compatible link
+                // NOTE: xdg-open compatible link looks like this:
+                // zoomus://zoom.us/join?action=join&confno=99917074685&pwd=RWprdkxOOEpUUU84ejRVZ09td1NPUT09
+                let re = regex::Regex::new("/j/(.*?)\\?pwd=(.*?)$").unwrap();
+                if let Some(cap) = re.captures_iter(&u).next() {
+                    return Some(format!(
+                        "zoomus://zoom.us/join?action=join&confno={}&pwd={}",
+                        &cap[1], &cap[2]
+                    ));
+                }
+                None
             });
-        }
 
-        data.lock().unwrap().calendar = output;
-
-        interval.tick().await;
+        output = Some(CalendarEvent {
+            title: event.summary.unwrap_or_default(),
+            time: format!("{} - {}", start.format("%l:%M%P"), end.format("%l:%M%P")),
+            start: start.timestamp(),
+            zoom_url,
+        });
     }
+
+    output
+}
+
+// Run the OAuth flow from scratch, replacing any saved tokens
+pub async fn authorize() -> Result<(), String> {
+    let mut cal = CalendarAPI::new(true).await?;
+    cal.hub
+        .events()
+        .list("primary")
+        .max_results(1)
+        .doit()
+        .await
+        .map_err(|e| e.to_string())?;
+    cal.save_tokens().await;
+    Ok(())
 }
 
 impl CalendarAPI {
-    async fn new() -> Self {
-        let secret = yup_oauth2::read_application_secret("/home/colinwm/.clientsecret.json")
+    async fn new(reauthorize: bool) -> Result<Self, String> {
+        let secret = yup_oauth2::read_application_secret(config::home_file(".clientsecret.json"))
             .await
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+
+        let store = CredentialStore::open().await;
+        let mut saved_tokens = store.get(TOKENS_KEY).await.unwrap_or_else(|e| {
+            eprintln!("couldn't read calendar tokens: {e}");
+            None
+        });
+        if saved_tokens.is_none() {
+            saved_tokens = std::fs::read_to_string(legacy_tokens()).ok();
+        }
+
+        // yup-oauth2 can only persist tokens to a file, so keep that file in the
+        // runtime dir (tmpfs) and mirror it into the credential store.
+        let token_cache = config::runtime_file("google-tokens.json");
+        if reauthorize {
+            saved_tokens = None;
+            let _ = std::fs::remove_file(&token_cache);
+        }
+        if let Some(tokens) = &saved_tokens {
+            if let Err(e) = credentials::write_private(&token_cache, tokens.as_bytes()) {
+                eprintln!("couldn't write tokens: {e}");
+            }
+        }
 
         let auth = yup_oauth2::InstalledFlowAuthenticator::builder(
             secret,
             yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
         )
-        .persist_tokens_to_disk("/home/colinwm/.console_gui_auth.json")
+        .persist_tokens_to_disk(&token_cache)
         .build()
         .await
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
         let hub = CalendarHub::new(
             hyper::Client::builder().build(hyper_rustls::HttpsConnector::with_native_roots()),
             auth,
         );
 
-        Self { hub }
+        let mut cal = Self {
+            hub,
+            store,
+            token_cache,
+            saved_tokens: None,
+        };
+        cal.save_tokens().await;
+        Ok(cal)
+    }
+
+    async fn save_tokens(&mut self) {
+        let tokens = match std::fs::read_to_string(&self.token_cache) {
+            Ok(t) => t,
+            Err(_) => return,
+        };
+        if self.saved_tokens.as_ref() == Some(&tokens) {
+            return;
+        }
+
+        match self.store.set(TOKENS_KEY, &tokens).await {
+            Ok(()) => {
+                self.saved_tokens = Some(tokens);
+                // Now that the tokens are stored safely, drop the plaintext copy
+                let _ = std::fs::remove_file(legacy_tokens());
+            }
+            Err(e) => eprintln!("couldn't save calendar tokens: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> chrono::DateTime<chrono::FixedOffset> {
+        chrono::DateTime::parse_from_rfc3339("2024-05-01T10:00:00-07:00").unwrap()
+    }
+
+    fn events(value: serde_json::Value) -> Vec<google_calendar3::api::Event> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn event(summary: &str, start: &str, end: &str) -> serde_json::Value {
+        serde_json::json!({
+            "summary": summary,
+            "start": { "dateTime": start },
+            "end": { "dateTime": end },
+        })
+    }
+
+    #[test]
+    fn picks_the_next_meeting() {
+        let selected = select_event(
+            events(serde_json::json!([
+                event(
+                    "Later",
+                    "2024-05-01T13:00:00-07:00",
+                    "2024-05-01T13:30:00-07:00"
+                ),
+                event(
+                    "Next",
+                    "2024-05-01T10:30:00-07:00",
+                    "2024-05-01T11:00:00-07:00"
+                ),
+            ])),
+            now(),
+        )
+        .unwrap();
+        assert_eq!(selected.title, "Next");
+        assert_eq!(selected.time, "10:30am - 11:00am");
+    }
+
+    #[test]
+    fn drops_meetings_mostly_over() {
+        // 80% over
+        let nearly_done = event(
+            "Done",
+            "2024-05-01T09:12:00-07:00",
+            "2024-05-01T10:12:00-07:00",
+        );
+        // 50% over
+        let halfway = event(
+            "Halfway",
+            "2024-05-01T09:30:00-07:00",
+            "2024-05-01T10:30:00-07:00",
+        );
+        let selected = select_event(events(serde_json::json!([nearly_done, halfway])), now());
+        assert_eq!(selected.unwrap().title, "Halfway");
+    }
+
+    #[test]
+    fn prefers_accepted_short_meetings_at_the_same_time() {
+        let mut long = event(
+            "Long",
+            "2024-05-01T11:00:00-07:00",
+            "2024-05-01T13:00:00-07:00",
+        );
+        long["attendees"] = serde_json::json!([{ "self": true, "responseStatus": "accepted" }]);
+        let mut tentative = event(
+            "Tentative",
+            "2024-05-01T11:00:00-07:00",
+            "2024-05-01T11:30:00-07:00",
+        );
+        tentative["attendees"] =
+            serde_json::json!([{ "self": true, "responseStatus": "tentative" }]);
+        let mut declined = event(
+            "Declined",
+            "2024-05-01T10:15:00-07:00",
+            "2024-05-01T10:30:00-07:00",
+        );
+        declined["attendees"] = serde_json::json!([{ "self": true, "responseStatus": "declined" }]);
+
+        let selected = select_event(
+            events(serde_json::json!([tentative, declined, long])),
+            now(),
+        );
+        assert_eq!(selected.unwrap().title, "Long");
+    }
+
+    #[test]
+    fn skips_malformed_events() {
+        let mut cancelled = event(
+            "Cancelled",
+            "2024-05-01T10:10:00-07:00",
+            "2024-05-01T10:20:00-07:00",
+        );
+        cancelled["status"] = "cancelled".into();
+        let selected = select_event(
+            events(serde_json::json!([
+                cancelled,
+                event("Bad start", "10:15", "2024-05-01T10:30:00-07:00"),
+                event("Bad end", "2024-05-01T10:15:00-07:00", "soon"),
+                { "summary": "No end", "start": { "dateTime": "2024-05-01T10:15:00-07:00" } },
+                { "summary": "All day", "start": { "date": "2024-05-01" }, "end": { "date": "2024-05-02" } },
+                event("Fine", "2024-05-01T12:00:00-07:00", "2024-05-01T12:30:00-07:00"),
+            ])),
+            now(),
+        );
+        assert_eq!(selected.unwrap().title, "Fine");
+        assert!(select_event(Vec::new(), now()).is_none());
     }
 }
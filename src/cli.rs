@@ -0,0 +1,208 @@
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+use crate::config::Config;
+use crate::credentials::{self, CredentialStore, Token};
+#[cfg(feature = "dev")]
+use crate::mock_github;
+use crate::{calendar, control, keyboard, keymap, recording, snapshot};
+
+const USAGE: &str = "usage: console-gui [command]
+
+commands:
+  run [--dev]              start the console (the default). --dev takes input from
+                           the window: 1-4, Esc, Enter, D and the arrow keys
+  notify <text>            show a toast in the running console
+  key <key>                press a key: lcd1-lcd4, abort, execute, danger, slider0-slider5
+  page <page>              switch page: home, shortcuts, pull_requests
+  refresh                  refetch all data now
+  record <file>|stop       save the key events the console receives to a file
+  replay <file>            play recorded key events back into the console
+  snapshot <fixture> [--page <page>] [--events <file>] [--size WxH]
+           [--now <rfc3339>] [--expect <file> [--update]]
+                           render a cache-format fixture without a window and print
+                           its shapes, or compare them with (or update) a snapshot.
+                           The clock is pinned to when the fixture was saved
+  mock-github <fixture> [--listen <addr>]
+                           answer GitHub API requests from recorded responses, on
+                           127.0.0.1:8789 by default. Set an account's api_url to
+                           http://127.0.0.1:8789 to use it. Only in builds with
+                           the dev feature
+  dump-state               print the running console's state as JSON
+  check-config             validate the config and look for credentials
+  learn-keys               record which physical key is which, for a new keyboard
+  auth <provider>          (re)authorize: google, github, gitlab or webhook";
+
+pub struct Run {
+    // Take input from the window rather than the console's keyboard
+    pub dev: bool,
+}
+
+// Runs a command given on the command line. Returns Some if the GUI should start instead.
+pub async fn run(args: &[String]) -> Option<Run> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let result = match args.as_slice() {
+        [] | ["run"] => return Some(Run { dev: false }),
+        ["run", "--dev"] => return Some(Run { dev: true }),
+        ["notify", text @ ..] if !text.is_empty() => {
+            send(serde_json::json!({ "cmd": "toast", "text": text.join(" ") }))
+                .await
+                .map(drop)
+        }
+        ["key", key] => match keyboard::Key::from_name(key) {
+            Some(key) => send(serde_json::json!({ "cmd": "key", "key": key }))
+                .await
+                .map(drop),
+            None => Err(format!("unknown key {key}")),
+        },
+        ["page", page] => send(serde_json::json!({ "cmd": "page", "page": page }))
+            .await
+            .map(drop),
+        ["record", "stop"] => send(serde_json::json!({ "cmd": "stop_recording" }))
+            .await
+            .map(drop),
+        ["record", path] => match std::env::current_dir() {
+            // The console may be running from another directory
+            Ok(dir) => send(serde_json::json!({ "cmd": "record", "path": dir.join(path) }))
+                .await
+                .map(drop),
+            Err(e) => Err(e.to_string()),
+        },
+        ["replay", path] => replay(std::path::Path::new(path)).await,
+        ["refresh"] => send(serde_json::json!({ "cmd": "refresh" }))
+            .await
+            .map(drop),
+        ["dump-state"] => match send(serde_json::json!({ "cmd": "state" })).await {
+            Ok(state) => serde_json::to_string_pretty(&state)
+                .map(|s| println!("{s}"))
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        },
+        ["snapshot", fixture, options @ ..] => snapshot::run(fixture, options),
+        #[cfg(feature = "dev")]
+        ["mock-github", fixture] => {
+            mock_github::serve(std::path::Path::new(fixture), "127.0.0.1:8789").await
+        }
+        #[cfg(feature = "dev")]
+        ["mock-github", fixture, "--listen", listen] => {
+            mock_github::serve(std::path::Path::new(fixture), listen).await
+        }
+        ["check-config"] => check_config().await,
+        ["learn-keys"] => keymap::learn(),
+        ["auth", provider] => auth(provider).await,
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("console-gui: {e}");
+        std::process::exit(1);
+    }
+    None
+}
+
+// Send one request to a running console over the control socket
+async fn send(request: serde_json::Value) -> Result<serde_json::Value, String> {
+    let path = control::socket_path();
+    let stream = tokio::net::UnixStream::connect(&path)
+        .await
+        .map_err(|e| format!("console isn't running? ({}: {e})", path.display()))?;
+
+    let (reader, mut writer) = stream.into_split();
+    let mut line = request.to_string();
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    tokio::io::BufReader::new(reader)
+        .read_line(&mut response)
+        .await
+        .map_err(|e| e.to_string())?;
+    let response: serde_json::Value =
+        serde_json::from_str(&response).map_err(|e| format!("bad response: {e}"))?;
+
+    if response["ok"].as_bool() != Some(true) {
+        return Err(response["error"].as_str().unwrap_or("failed").to_string());
+    }
+    Ok(response["result"].clone())
+}
+
+async fn replay(path: &std::path::Path) -> Result<(), String> {
+    let events = recording::load(path)?;
+    let start = tokio::time::Instant::now();
+    for (at, event) in events {
+        tokio::time::sleep_until(start + at).await;
+        send(serde_json::json!({ "cmd": "event", "event": event })).await?;
+    }
+    Ok(())
+}
+
+async fn check_config() -> Result<(), String> {
+    let path = Config::path();
+    let config = match Config::check() {
+        Ok(Some(config)) => {
+            println!("{}: ok", path.display());
+            config
+        }
+        Ok(None) => {
+            println!("{}: not found, using defaults", path.display());
+            Config::default()
+        }
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    config
+        .input_source()
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let keymap = keymap::Keymap::path();
+    match keymap::Keymap::check() {
+        Ok(Some(_)) => println!("{}: ok", keymap.display()),
+        Ok(None) => println!("{}: not found, using the default keys", keymap.display()),
+        Err(e) => return Err(format!("{}: {e}", keymap.display())),
+    }
+
+    let store = CredentialStore::open().await;
+    println!("credentials: {}", store.describe());
+
+    let mut missing = 0;
+    for token in Token::all(&config) {
+        let found = token.get(&store).await.is_some();
+        println!(
+            "  {}: {}",
+            token.description,
+            if found { "found" } else { "missing" }
+        );
+        if !found {
+            missing += 1;
+        }
+    }
+
+    match missing {
+        0 => Ok(()),
+        n => Err(format!("{n} token(s) missing, run `console-gui auth`")),
+    }
+}
+
+async fn auth(provider: &str) -> Result<(), String> {
+    let config = Config::load();
+    let store = CredentialStore::open().await;
+    let tokens: Vec<Token> = match provider {
+        "google" => return calendar::authorize().await,
+        "github" => config.github_accounts.iter().map(Token::github).collect(),
+        "gitlab" => config.gitlab_accounts.iter().map(Token::gitlab).collect(),
+        "webhook" => config.webhook.iter().map(Token::webhook).collect(),
+        _ => return Err(format!("unknown provider {provider}")),
+    };
+
+    if tokens.is_empty() {
+        return Err(format!("no {provider} accounts configured"));
+    }
+    for token in tokens {
+        credentials::prompt(&store, &token).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, FixedOffset, TimeZone};
+use std::sync::Arc;
+
+// Where the current time comes from. Everything that shows or filters by the time
+// asks the clock rather than chrono, so it can be run at a fixed time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<FixedOffset>;
+
+    fn timestamp(&self) -> i64 {
+        self.now().timestamp()
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        chrono::Local::now().into()
+    }
+}
+
+pub struct FixedClock(pub DateTime<FixedOffset>);
+
+impl FixedClock {
+    // A UTC time, e.g. from a cache's saved_at
+    pub fn at(timestamp: i64) -> Self {
+        Self(FixedOffset::east(0).timestamp(timestamp, 0))
+    }
+
+    pub fn parse(time: &str) -> Result<Self, String> {
+        DateTime::parse_from_rfc3339(time)
+            .map(Self)
+            .map_err(|e| format!("bad time {time}: {e}"))
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.0
+    }
+}
+
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
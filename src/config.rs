@@ -0,0 +1,240 @@
+use serde::Deserialize;
+
+use crate::keyboard;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // How far back the SUB tab looks for closed pull requests
+    pub submitted_window_days: i64,
+    pub github_accounts: Vec<GitHubAccount>,
+    pub gitlab_accounts: Vec<GitLabAccount>,
+    pub desktop_notifications: DesktopNotifications,
+    pub webhook: Option<WebhookConfig>,
+    // How long a key is held before it counts as a long press
+    pub long_press_ms: u64,
+    // How close together two presses are to count as a double tap
+    pub double_tap_ms: u64,
+    // How close together two keys go down to count as a chord
+    pub chord_ms: u64,
+    // Which keyboards drive the console. Empty means every keyboard on the seat.
+    pub input_devices: Vec<DeviceFilter>,
+    pub input_backend: keyboard::Backend,
+    // Grab the input devices exclusively; only the evdev backend can, and only with
+    // input_devices set
+    pub input_grab: bool,
+    // Multiplies every size on screen. Unset, it follows the window's size.
+    pub ui_scale: Option<f32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            submitted_window_days: 14,
+            github_accounts: vec![GitHubAccount {
+                name: "github".to_string(),
+                api_url: default_api_url(),
+                graphql_url: None,
+                user: "colinwm".to_string(),
+                token_env: default_token_env(),
+            }],
+            gitlab_accounts: Vec::new(),
+            desktop_notifications: DesktopNotifications::Off,
+            webhook: None,
+            long_press_ms: 600,
+            double_tap_ms: 300,
+            chord_ms: 80,
+            input_devices: Vec::new(),
+            input_backend: keyboard::Backend::Libinput,
+            input_grab: false,
+            ui_scale: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GitHubAccount {
+    // Short tag shown next to items from this account
+    pub name: String,
+    // REST API root, e.g. https://ghe.example.com/api/v3 for GitHub Enterprise
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    #[serde(default)]
+    pub graphql_url: Option<String>,
+    pub user: String,
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+}
+
+impl GitHubAccount {
+    pub fn graphql_url(&self) -> String {
+        if let Some(url) = &self.graphql_url {
+            return url.clone();
+        }
+
+        // GitHub Enterprise serves GraphQL from /api/graphql rather than /api/v3/graphql
+        match self.api_url.strip_suffix("/api/v3") {
+            Some(root) => format!("{root}/api/graphql"),
+            None => format!("{}/graphql", self.api_url),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GitLabAccount {
+    pub name: String,
+    // Instance root, e.g. https://gitlab.example.com
+    pub url: String,
+    pub user: String,
+    #[serde(default = "default_gitlab_token_env")]
+    pub token_env: String,
+}
+
+// A local listener for GitHub webhooks, forwarded by a relay or `gh webhook forward`
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookConfig {
+    #[serde(default = "default_webhook_listen")]
+    pub listen: String,
+    // The GitHub account whose sources are refreshed when an event arrives
+    pub account: String,
+    #[serde(default = "default_webhook_secret_env")]
+    pub secret_env: String,
+}
+
+// Picks out an input device; every field given has to match
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeviceFilter {
+    // Part of the name shown by `libinput list-devices`
+    pub name: Option<String>,
+    // USB IDs in hex, as shown by lsusb, e.g. "046d"
+    pub vendor: Option<String>,
+    pub product: Option<String>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, name: &str, vendor: u32, product: u32) -> bool {
+        let id_matches = |want: &Option<String>, id: u32| match want {
+            Some(want) => u32::from_str_radix(want.trim_start_matches("0x"), 16) == Ok(id),
+            None => true,
+        };
+
+        let name_matches = match &self.name {
+            Some(want) => name.to_lowercase().contains(&want.to_lowercase()),
+            None => true,
+        };
+        name_matches && id_matches(&self.vendor, vendor) && id_matches(&self.product, product)
+    }
+}
+
+// Whether to show freedesktop notifications on the home page
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DesktopNotifications {
+    Off,
+    // Own org.freedesktop.Notifications, replacing the usual notification daemon
+    Server,
+    // Eavesdrop on another daemon's notifications
+    Monitor,
+}
+
+fn default_webhook_listen() -> String {
+    "127.0.0.1:8787".to_string()
+}
+
+fn default_webhook_secret_env() -> String {
+    "WEBHOOK_SECRET".to_string()
+}
+
+fn default_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+fn default_token_env() -> String {
+    "PAT".to_string()
+}
+
+fn default_gitlab_token_env() -> String {
+    "GITLAB_TOKEN".to_string()
+}
+
+pub fn home_file(name: &str) -> std::path::PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/home/colinwm".to_string());
+    std::path::Path::new(&home).join(name)
+}
+
+// For per-session files (sockets, short-lived tokens), preferring the tmpfs runtime dir
+pub fn runtime_file(name: &str) -> std::path::PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => std::path::Path::new(&dir).join(format!("console-gui-{name}")),
+        Err(_) => {
+            std::env::temp_dir().join(format!("console-gui-{}-{name}", nix::unistd::getuid()))
+        }
+    }
+}
+
+impl Config {
+    pub fn key_timing(&self) -> keyboard::Timing {
+        keyboard::Timing {
+            long_press: std::time::Duration::from_millis(self.long_press_ms),
+            double_tap: std::time::Duration::from_millis(self.double_tap_ms),
+            chord: std::time::Duration::from_millis(self.chord_ms),
+        }
+    }
+
+    pub fn input_source(&self) -> Result<keyboard::Source, String> {
+        // Grabbing every keyboard on the seat would take the desktop's keyboard too
+        if self.input_grab && self.input_devices.is_empty() {
+            return Err("input_grab needs at least one input_devices filter".to_string());
+        }
+        Ok(keyboard::Source {
+            backend: self.input_backend,
+            grab: self.input_grab,
+            devices: self.input_devices.clone(),
+        })
+    }
+
+    pub fn path() -> std::path::PathBuf {
+        home_file(".console_gui_config.json")
+    }
+
+    // Like load, but reports a broken config instead of falling back to defaults.
+    // Ok(None) means there's no config file.
+    pub fn check() -> Result<Option<Self>, String> {
+        let contents = match std::fs::read_to_string(Self::path()) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn load() -> Self {
+        match Self::check() {
+            Ok(config) => config.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("invalid config {}: {}", Self::path().display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grab_needs_a_device_filter() {
+        let mut config = Config {
+            input_backend: keyboard::Backend::Evdev,
+            input_grab: true,
+            ..Config::default()
+        };
+        assert!(config.input_source().is_err());
+
+        config.input_devices = serde_json::from_str(r#"[{"name": "Console"}]"#).unwrap();
+        assert!(config.input_source().unwrap().grab);
+    }
+}
@@ -0,0 +1,200 @@
+use nix::sys::stat::{umask, Mode};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+use crate::{config, keyboard, recording, AppState, Notification, PageState};
+
+// A newline-delimited JSON protocol on a Unix socket, so scripts can drive the
+// console. Each request line gets exactly one response line, e.g.
+//   {"cmd": "page", "page": "pull_requests"}  ->  {"ok": true}
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Page {
+        page: Page,
+    },
+    Key {
+        key: keyboard::Key,
+    },
+    Event {
+        event: keyboard::KeyEvent,
+    },
+    Toast {
+        text: String,
+    },
+    Notify {
+        title: String,
+        #[serde(default)]
+        repository: String,
+        #[serde(default)]
+        url: String,
+    },
+    Refresh,
+    State,
+    // Start saving key events to a file, for `console-gui replay`
+    Record {
+        path: std::path::PathBuf,
+    },
+    StopRecording,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Page {
+    Home,
+    Shortcuts,
+    PullRequests,
+}
+
+impl Page {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        serde_json::from_value(serde_json::json!(name)).map_err(|_| format!("unknown page {name}"))
+    }
+
+    pub(crate) fn state(self) -> PageState {
+        match self {
+            Self::Home => PageState::home(),
+            Self::Shortcuts => PageState::shortcuts(),
+            Self::PullRequests => PageState::pull_requests(),
+        }
+    }
+}
+
+pub fn socket_path() -> std::path::PathBuf {
+    config::runtime_file("control.sock")
+}
+
+pub async fn serve(data: Arc<Mutex<AppState>>) {
+    let path = socket_path();
+    // A socket left over from a previous run would make bind fail
+    let _ = std::fs::remove_file(&path);
+    // The socket is created 0600 so no one else can connect, not even in the moment
+    // between binding and a chmod
+    let old = umask(Mode::from_bits_truncate(0o177));
+    let listener = tokio::net::UnixListener::bind(&path);
+    umask(old);
+    let listener = match listener {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("couldn't listen on {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("control socket: {:?}", e);
+                continue;
+            }
+        };
+
+        let data = data.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = tokio::io::BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(req) => data.lock().unwrap().handle_request(req),
+                    Err(e) => Err(e.to_string()),
+                };
+                let response = match response {
+                    Ok(serde_json::Value::Null) => serde_json::json!({ "ok": true }),
+                    Ok(value) => serde_json::json!({ "ok": true, "result": value }),
+                    Err(e) => serde_json::json!({ "ok": false, "error": e }),
+                };
+
+                let mut out = response.to_string();
+                out.push('\n');
+                if writer.write_all(out.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl AppState {
+    pub fn handle_request(&mut self, req: Request) -> Result<serde_json::Value, String> {
+        match req {
+            Request::Page { page } => {
+                self.page = page.state();
+                self.cache_dirty = true;
+            }
+            Request::Key { key } => {
+                self.handle_key_event(keyboard::KeyEvent::Press(key), std::time::Instant::now())
+            }
+            Request::Event { event } => self.handle_key_event(event, std::time::Instant::now()),
+            Request::Toast { text } => self.show_toast(text),
+            Request::Notify {
+                title,
+                repository,
+                url,
+            } => {
+                self.notifications.insert(
+                    0,
+                    Notification {
+                        title,
+                        action: "notify".to_string(),
+                        repository,
+                        account: "control".to_string(),
+                        time: self.clock.timestamp(),
+                        url,
+                    },
+                );
+            }
+            Request::Refresh => self.refresh_all(),
+            Request::Record { path } => {
+                self.recorder = Some(recording::Recorder::create(&path)?);
+            }
+            Request::StopRecording => {
+                if self.recorder.take().is_none() {
+                    return Err("not recording".to_string());
+                }
+            }
+            Request::State => return self.to_json(),
+        }
+
+        if let Some(frame) = self.frame.as_ref() {
+            frame.request_repaint();
+        }
+        Ok(serde_json::Value::Null)
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, String> {
+        let sources: serde_json::Map<String, serde_json::Value> = self
+            .sources
+            .iter()
+            .map(|(name, health)| {
+                let health = serde_json::json!({
+                    "kind": format!("{:?}", health.kind),
+                    "last_success": health.last_success,
+                    "last_error": health.last_error,
+                });
+                (name.clone(), health)
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "page": serde_json::to_value(&self.page).map_err(|e| e.to_string())?,
+            "clock": self.clock_text().trim(),
+            "calendar": self.calendar,
+            "notifications": self.notifications,
+            "desktop_notifications": self.desktop_notifications,
+            "open_prs": self.open_prs,
+            "draft_prs": self.draft_prs,
+            "closed_prs": self.closed_prs,
+            "review_prs": self.review_prs,
+            "sources": sources,
+            "toast": self.toast.as_ref().map(|t| &t.text),
+            "cached_at": self.cached_at,
+            "keyboard_connected": self.keyboard_connected,
+        }))
+    }
+}
@@ -0,0 +1,355 @@
+use ring::aead;
+use ring::rand::SecureRandom;
+use secret_service::{EncryptionType, SecretService};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+use crate::config::{self, Config, GitHubAccount, GitLabAccount, WebhookConfig};
+
+// Stores secrets in the Secret Service (gnome-keyring, kwallet, ...) when one is
+// running on the session bus, or in an encrypted file otherwise.
+pub enum CredentialStore {
+    SecretService,
+    File(std::path::PathBuf),
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+impl CredentialStore {
+    pub async fn open() -> Self {
+        if let Ok(ss) = SecretService::connect(EncryptionType::Dh).await {
+            if ss.get_default_collection().await.is_ok() {
+                return Self::SecretService;
+            }
+        }
+
+        Self::File(config::home_file(".console_gui_credentials"))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::SecretService => "Secret Service".to_string(),
+            Self::File(path) => path.display().to_string(),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, String> {
+        match self {
+            Self::SecretService => {
+                let ss = SecretService::connect(EncryptionType::Dh)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let items = ss
+                    .search_items(attributes(key))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let item = match items.unlocked.first().or_else(|| items.locked.first()) {
+                    Some(item) => item,
+                    None => return Ok(None),
+                };
+                if item.is_locked().await.map_err(|e| e.to_string())? {
+                    item.unlock().await.map_err(|e| e.to_string())?;
+                }
+
+                let secret = item.get_secret().await.map_err(|e| e.to_string())?;
+                String::from_utf8(secret)
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+            Self::File(path) => Ok(read_file(path)?.remove(key)),
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        match self {
+            Self::SecretService => {
+                let ss = SecretService::connect(EncryptionType::Dh)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let collection = ss
+                    .get_default_collection()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if collection.is_locked().await.map_err(|e| e.to_string())? {
+                    collection.unlock().await.map_err(|e| e.to_string())?;
+                }
+
+                collection
+                    .create_item(
+                        &format!("console-gui {key}"),
+                        attributes(key),
+                        value.as_bytes(),
+                        true,
+                        "text/plain",
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            Self::File(path) => {
+                let mut secrets = read_file(path)?;
+                secrets.insert(key.to_string(), value.to_string());
+                write_file(path, &secrets)
+            }
+        }
+    }
+}
+
+fn attributes(key: &str) -> HashMap<&str, &str> {
+    HashMap::from([("application", "console-gui"), ("key", key)])
+}
+
+// The file is encrypted with a random key kept beside it in a second file that
+// only the user can read. That keeps the tokens out of anything that copies the
+// credentials file alone, but not from other programs running as the user.
+fn key_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".key");
+    name.into()
+}
+
+fn read_key(path: &std::path::Path) -> Result<Option<Vec<u8>>, String> {
+    let path = key_path(path);
+    match std::fs::read_to_string(&path) {
+        Ok(key) => base64::decode(key.trim())
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn create_key(path: &std::path::Path) -> Result<Vec<u8>, String> {
+    let mut key = vec![0u8; 32];
+    ring::rand::SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| "couldn't generate key")?;
+    write_private(&key_path(path), base64::encode(&key).as_bytes())?;
+    Ok(key)
+}
+
+fn file_key(secret: &[u8], salt: &[u8]) -> aead::LessSafeKey {
+    let key: aead::UnboundKey = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, salt)
+        .extract(secret)
+        .expand(&[b"console-gui credentials"], &aead::CHACHA20_POLY1305)
+        .unwrap()
+        .into();
+    aead::LessSafeKey::new(key)
+}
+
+fn read_file(path: &std::path::Path) -> Result<HashMap<String, String>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let file: EncryptedFile = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    let salt = base64::decode(&file.salt).map_err(|e| e.to_string())?;
+    let nonce = base64::decode(&file.nonce).map_err(|e| e.to_string())?;
+    let mut data = base64::decode(&file.data).map_err(|e| e.to_string())?;
+
+    let secret =
+        read_key(path)?.ok_or_else(|| format!("missing key {}", key_path(path).display()))?;
+    let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| "invalid nonce")?;
+    let plaintext = file_key(&secret, &salt)
+        .open_in_place(nonce, aead::Aad::empty(), &mut data)
+        .map_err(|_| format!("couldn't decrypt {}", path.display()))?;
+
+    serde_json::from_slice(plaintext).map_err(|e| e.to_string())
+}
+
+fn write_file(path: &std::path::Path, secrets: &HashMap<String, String>) -> Result<(), String> {
+    let secret = match read_key(path)? {
+        Some(secret) => secret,
+        None => create_key(path)?,
+    };
+
+    let rng = ring::rand::SystemRandom::new();
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    rng.fill(&mut salt).map_err(|_| "couldn't generate salt")?;
+    rng.fill(&mut nonce)
+        .map_err(|_| "couldn't generate nonce")?;
+
+    let mut data = serde_json::to_vec(secrets).map_err(|e| e.to_string())?;
+    file_key(&secret, &salt)
+        .seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::empty(),
+            &mut data,
+        )
+        .map_err(|_| "couldn't encrypt credentials")?;
+
+    let file = EncryptedFile {
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        data: base64::encode(data),
+    };
+    let contents = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+    write_private(path, &contents)
+}
+
+// Writes a file only the user can read. It's written then renamed, so a crash
+// mid-write never loses what was there.
+pub(crate) fn write_private(path: &std::path::Path, contents: &[u8]) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+
+    let mut out = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .map_err(|e| format!("{}: {e}", tmp.display()))?;
+    // A leftover tmp file may have looser permissions
+    out.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .and_then(|_| out.write_all(contents))
+        .and_then(|_| out.sync_all())
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+// An API token for one configured account
+pub struct Token {
+    pub key: String,
+    pub env: String,
+    pub description: String,
+}
+
+impl Token {
+    pub fn github(account: &GitHubAccount) -> Self {
+        Self {
+            key: format!("github:{}", account.name),
+            env: account.token_env.clone(),
+            description: format!("GitHub token for {} ({})", account.name, account.api_url),
+        }
+    }
+
+    pub fn gitlab(account: &GitLabAccount) -> Self {
+        Self {
+            key: format!("gitlab:{}", account.name),
+            env: account.token_env.clone(),
+            description: format!("GitLab token for {} ({})", account.name, account.url),
+        }
+    }
+
+    pub fn webhook(webhook: &WebhookConfig) -> Self {
+        Self {
+            key: format!("webhook:{}", webhook.account),
+            env: webhook.secret_env.clone(),
+            description: format!("GitHub webhook secret for {}", webhook.account),
+        }
+    }
+
+    pub fn all(config: &Config) -> Vec<Self> {
+        config
+            .github_accounts
+            .iter()
+            .map(Self::github)
+            .chain(config.gitlab_accounts.iter().map(Self::gitlab))
+            .chain(config.webhook.iter().map(Self::webhook))
+            .collect()
+    }
+
+    pub async fn get(&self, store: &CredentialStore) -> Option<String> {
+        match store.get(&self.key).await {
+            Ok(Some(token)) => return Some(token),
+            Ok(None) => (),
+            Err(e) => eprintln!("couldn't read credentials: {e}"),
+        }
+
+        // Fall back to the environment for setups that predate the credential store
+        std::env::var(&self.env).ok()
+    }
+}
+
+// First-run setup: ask for any tokens we don't have yet
+pub async fn setup(config: &Config) {
+    if !nix::unistd::isatty(0).unwrap_or(false) {
+        return;
+    }
+
+    let store = CredentialStore::open().await;
+    for token in Token::all(config) {
+        if token.get(&store).await.is_none() {
+            prompt(&store, &token).await;
+        }
+    }
+}
+
+// Ask for a token and save it, replacing any existing one
+pub async fn prompt(store: &CredentialStore, token: &Token) {
+    let value = match read_secret(&format!("{}: ", token.description)) {
+        Some(t) if !t.is_empty() => t,
+        _ => return,
+    };
+
+    match store.set(&token.key, &value).await {
+        Ok(()) => println!("saved token to {}", store.describe()),
+        Err(e) => eprintln!("couldn't save token: {e}"),
+    }
+}
+
+pub fn read_secret(prompt: &str) -> Option<String> {
+    use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+    use std::io::Write;
+
+    print!("{prompt}");
+    std::io::stdout().flush().ok()?;
+
+    // Turn off echo while the secret is typed
+    let original = tcgetattr(0).ok();
+    if let Some(original) = &original {
+        let mut silent = original.clone();
+        silent.local_flags.remove(LocalFlags::ECHO);
+        let _ = tcsetattr(0, SetArg::TCSANOW, &silent);
+    }
+
+    let mut line = String::new();
+    let result = std::io::stdin().read_line(&mut line);
+
+    if let Some(original) = &original {
+        let _ = tcsetattr(0, SetArg::TCSANOW, original);
+    }
+    println!();
+
+    result.ok()?;
+    Some(line.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_round_trips_with_a_private_key() {
+        let dir = std::env::temp_dir().join(format!("console-gui-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials");
+
+        let secrets = HashMap::from([("github:work".to_string(), "ghp_token".to_string())]);
+        write_file(&path, &secrets).unwrap();
+        assert_eq!(read_file(&path).unwrap(), secrets);
+
+        for path in [path.clone(), key_path(&path)] {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", path.display());
+        }
+
+        // Without the key file the credentials can't be read
+        std::fs::remove_file(key_path(&path)).unwrap();
+        assert!(read_file(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zbus::zvariant::OwnedValue;
+use zbus::{MessageType, SignalContext};
+
+use crate::config::DesktopNotifications;
+use crate::{AppState, DesktopNotification};
+
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+// Close reasons from the notification spec
+const DISMISSED: u32 = 2;
+const CLOSED_BY_CALL: u32 = 3;
+
+// Only keep this many, since nothing expires them
+const MAX_NOTIFICATIONS: usize = 50;
+
+// What the user did with a desktop notification on the console
+pub enum Request {
+    Invoke(u32, String),
+    Dismiss(u32),
+}
+
+pub async fn run(
+    data: Arc<Mutex<AppState>>,
+    mode: DesktopNotifications,
+    requests: tokio::sync::mpsc::UnboundedReceiver<Request>,
+) {
+    let result = match mode {
+        DesktopNotifications::Off => return,
+        DesktopNotifications::Server => serve(data, requests).await,
+        DesktopNotifications::Monitor => monitor(data, requests).await,
+    };
+    if let Err(e) = result {
+        eprintln!("desktop notifications: {e}");
+    }
+}
+
+// Notify's arguments: app name, replaces ID, icon, summary, body, actions, hints, timeout
+type NotifyArgs = (
+    String,
+    u32,
+    String,
+    String,
+    String,
+    Vec<String>,
+    HashMap<String, OwnedValue>,
+    i32,
+);
+
+fn notification(id: u32, args: NotifyArgs, time: i64) -> DesktopNotification {
+    let (app, _, _, summary, body, actions, _, _) = args;
+    DesktopNotification {
+        id,
+        app,
+        summary,
+        body,
+        // Actions arrive as a flat list of key, label pairs
+        actions: actions
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect(),
+        time,
+    }
+}
+
+struct Server {
+    data: Arc<Mutex<AppState>>,
+    last_id: u32,
+}
+
+#[zbus::dbus_interface(name = "org.freedesktop.Notifications")]
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let id = if replaces_id > 0 {
+            replaces_id
+        } else {
+            self.last_id += 1;
+            self.last_id
+        };
+
+        let args = (
+            app_name,
+            replaces_id,
+            app_icon,
+            summary,
+            body,
+            actions,
+            hints,
+            expire_timeout,
+        );
+        let mut state = self.data.lock().unwrap();
+        let time = state.clock.timestamp();
+        state.add_desktop_notification(notification(id, args, time));
+        id
+    }
+
+    async fn close_notification(
+        &self,
+        id: u32,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.data
+            .lock()
+            .unwrap()
+            .desktop_notifications
+            .retain(|n| n.id != id);
+        Self::notification_closed(&ctxt, id, CLOSED_BY_CALL).await?;
+        Ok(())
+    }
+
+    fn get_capabilities(&self) -> Vec<String> {
+        vec!["actions".to_string(), "body".to_string()]
+    }
+
+    #[dbus_interface(out_args("name", "vendor", "version", "spec_version"))]
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            "console-gui".to_string(),
+            "console-gui".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+            "1.2".to_string(),
+        )
+    }
+
+    #[dbus_interface(signal)]
+    async fn notification_closed(
+        ctxt: &SignalContext<'_>,
+        id: u32,
+        reason: u32,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn action_invoked(
+        ctxt: &SignalContext<'_>,
+        id: u32,
+        action_key: &str,
+    ) -> zbus::Result<()>;
+}
+
+// Own org.freedesktop.Notifications, so applications send notifications straight to us
+async fn serve(
+    data: Arc<Mutex<AppState>>,
+    mut requests: tokio::sync::mpsc::UnboundedReceiver<Request>,
+) -> zbus::Result<()> {
+    let conn = zbus::ConnectionBuilder::session()?
+        .serve_at(PATH, Server { data, last_id: 0 })?
+        .name(INTERFACE)?
+        .build()
+        .await?;
+    let ctxt = SignalContext::new(&conn, PATH)?;
+
+    while let Some(req) = requests.recv().await {
+        match req {
+            Request::Invoke(id, key) => {
+                Server::action_invoked(&ctxt, id, &key).await?;
+                Server::notification_closed(&ctxt, id, DISMISSED).await?;
+            }
+            Request::Dismiss(id) => Server::notification_closed(&ctxt, id, DISMISSED).await?,
+        }
+    }
+    Ok(())
+}
+
+// Watch the traffic to another notification daemon. Actions can't be invoked this
+// way, since only the owning daemon can emit ActionInvoked, so both requests just
+// close the notification.
+async fn monitor(
+    data: Arc<Mutex<AppState>>,
+    mut requests: tokio::sync::mpsc::UnboundedReceiver<Request>,
+) -> zbus::Result<()> {
+    // A monitor connection can't send anything, so closing needs a second connection
+    let conn = zbus::Connection::session().await?;
+    let monitor = zbus::Connection::session().await?;
+    zbus::fdo::MonitoringProxy::new(&monitor)
+        .await?
+        .become_monitor(
+            &[
+                "type='method_call',interface='org.freedesktop.Notifications',member='Notify'",
+                "type='method_return'",
+                "type='signal',interface='org.freedesktop.Notifications',member='NotificationClosed'",
+            ],
+            0,
+        )
+        .await?;
+    let mut messages = zbus::MessageStream::from(&monitor);
+
+    // Notify calls waiting for the daemon's reply, which carries the ID.
+    // Keyed by the caller and the call's serial.
+    let mut pending: HashMap<(String, u32), NotifyArgs> = HashMap::new();
+
+    loop {
+        let msg = tokio::select! {
+            msg = messages.next() => match msg {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) => {
+                    eprintln!("desktop notifications: {e}");
+                    continue;
+                }
+                None => return Ok(()),
+            },
+            req = requests.recv() => {
+                let id = match req {
+                    Some(Request::Invoke(id, _)) | Some(Request::Dismiss(id)) => id,
+                    None => return Ok(()),
+                };
+                if let Err(e) = conn
+                    .call_method(Some(INTERFACE), PATH, Some(INTERFACE), "CloseNotification", &id)
+                    .await
+                {
+                    eprintln!("couldn't close notification {id}: {e}");
+                }
+                continue;
+            }
+        };
+
+        // One malformed message shouldn't stop the monitor
+        let header = match msg.header() {
+            Ok(header) => header,
+            Err(e) => {
+                eprintln!("desktop notifications: {e}");
+                continue;
+            }
+        };
+        match msg.message_type() {
+            MessageType::MethodCall => {
+                let sender = header
+                    .sender()
+                    .ok()
+                    .flatten()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default();
+                let serial = *msg.primary_header().serial_num().unwrap_or(&0);
+                if let Ok(args) = msg.body::<NotifyArgs>() {
+                    // Replies can go missing (e.g. the daemon crashed), so don't grow forever
+                    if pending.len() > 100 {
+                        pending.clear();
+                    }
+                    pending.insert((sender, serial), args);
+                }
+            }
+            MessageType::MethodReturn => {
+                let destination = header
+                    .destination()
+                    .ok()
+                    .flatten()
+                    .map(|d| d.to_string())
+                    .unwrap_or_default();
+                let serial = header.reply_serial().ok().flatten().unwrap_or(0);
+                if let Some(args) = pending.remove(&(destination, serial)) {
+                    let id = match msg.body::<u32>() {
+                        Ok(id) => id,
+                        Err(e) => {
+                            eprintln!("desktop notifications: {e}");
+                            continue;
+                        }
+                    };
+                    let mut state = data.lock().unwrap();
+                    let time = state.clock.timestamp();
+                    state.add_desktop_notification(notification(id, args, time));
+                }
+            }
+            MessageType::Signal => {
+                if let Ok((id, _)) = msg.body::<(u32, u32)>() {
+                    data.lock()
+                        .unwrap()
+                        .desktop_notifications
+                        .retain(|n| n.id != id);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl AppState {
+    fn add_desktop_notification(&mut self, notification: DesktopNotification) {
+        self.desktop_notifications
+            .retain(|n| n.id != notification.id);
+        self.desktop_notifications.insert(0, notification);
+        self.desktop_notifications.truncate(MAX_NOTIFICATIONS);
+
+        if let Some(frame) = self.frame.as_ref() {
+            frame.request_repaint();
+        }
+    }
+
+    // Remove a notification from the list, triggering its default action if asked to
+    pub fn close_desktop_notification(&mut self, idx: usize, invoke: bool) {
+        if idx >= self.desktop_notifications.len() {
+            return;
+        }
+        let notification = self.desktop_notifications.remove(idx);
+
+        let action = notification
+            .actions
+            .iter()
+            .find(|(key, _)| key == "default")
+            .or_else(|| notification.actions.first());
+        let req = match action {
+            Some((key, _)) if invoke => Request::Invoke(notification.id, key.clone()),
+            _ => Request::Dismiss(notification.id),
+        };
+        if let Some(desktop) = &self.desktop {
+            let _ = desktop.send(req);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use crate::config::DeviceFilter;
+use crate::keyboard::Input;
+
+// Reads /dev/input/event* directly, for setups without udev seat access
+// (containers, minimal kiosk installs). Hotplug is handled by rescanning.
+
+const EV_KEY: u16 = 1;
+const KEY_CNT: usize = 0x300;
+// Codes from here up are mouse/joystick buttons rather than keys
+const BTN_MISC: usize = 0x100;
+
+const RESCAN: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[repr(C)]
+#[derive(Default)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+nix::ioctl_read_buf!(eviocgname, b'E', 0x06, u8);
+nix::ioctl_read!(eviocgid, b'E', 0x02, InputId);
+nix::ioctl_read_buf!(eviocgbit_key, b'E', 0x20 + EV_KEY, u8);
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
+struct Device {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+}
+
+// Opens the device if it's a keyboard matching the filters
+fn open(
+    path: &std::path::Path,
+    devices: &[DeviceFilter],
+    grab: bool,
+) -> Result<Option<Device>, String> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    let fd = file.as_raw_fd();
+
+    let mut name = [0u8; 256];
+    let mut id = InputId::default();
+    let mut keys = [0u8; KEY_CNT / 8];
+    unsafe {
+        eviocgname(fd, &mut name).map_err(|e| e.to_string())?;
+        eviocgid(fd, &mut id).map_err(|e| e.to_string())?;
+        eviocgbit_key(fd, &mut keys).map_err(|e| e.to_string())?;
+    }
+    let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    let name = String::from_utf8_lossy(&name[..len]);
+
+    let is_keyboard = keys[..BTN_MISC / 8].iter().any(|b| *b != 0);
+    let wanted = devices.is_empty()
+        || devices
+            .iter()
+            .any(|d| d.matches(&name, id.vendor as u32, id.product as u32));
+    if !is_keyboard || !wanted {
+        return Ok(None);
+    }
+
+    // Keep the console's keys from reaching the rest of the desktop
+    if grab {
+        unsafe { eviocgrab(fd, 1) }.map_err(|e| format!("couldn't grab {name}: {e}"))?;
+    }
+
+    Ok(Some(Device {
+        path: path.to_path_buf(),
+        file,
+    }))
+}
+
+pub fn run<F: FnMut(Input) -> bool>(
+    devices: &[DeviceFilter],
+    grab: bool,
+    callback: &mut F,
+) -> Result<(), String> {
+    let mut keyboards: Vec<Device> = Vec::new();
+    // Event nodes that aren't keyboards we want, so they're only checked once
+    let mut ignored = std::collections::HashSet::new();
+    // Nodes that couldn't be opened, e.g. for permissions or another grab. They're
+    // retried on every rescan, but only logged the first time.
+    let mut failing = std::collections::HashSet::new();
+    let mut last_scan: Option<std::time::Instant> = None;
+
+    // struct input_event: a timeval, then u16 type, u16 code and i32 value
+    let time_size = std::mem::size_of::<nix::libc::timeval>();
+    let event_size = time_size + 8;
+    let mut buf = vec![0u8; event_size * 64];
+
+    loop {
+        if last_scan.map_or(true, |t| t.elapsed() > RESCAN) {
+            last_scan = Some(std::time::Instant::now());
+            ignored.retain(|p: &std::path::PathBuf| p.exists());
+            failing.retain(|p: &std::path::PathBuf| p.exists());
+
+            let entries = std::fs::read_dir("/dev/input").map_err(|e| e.to_string())?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_event = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with("event"));
+                if !is_event || ignored.contains(&path) || keyboards.iter().any(|k| k.path == path)
+                {
+                    continue;
+                }
+
+                match open(&path, devices, grab) {
+                    Ok(Some(device)) => {
+                        failing.remove(&path);
+                        keyboards.push(device);
+                        if !callback(Input::Connected(true)) {
+                            return Ok(());
+                        }
+                    }
+                    Ok(None) => {
+                        failing.remove(&path);
+                        ignored.insert(path);
+                    }
+                    Err(e) => {
+                        if failing.insert(path.clone()) {
+                            eprintln!("input: {}: {e}", path.display());
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut removed = false;
+        let mut idx = 0;
+        while idx < keyboards.len() {
+            let n = match keyboards[idx].file.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    idx += 1;
+                    continue;
+                }
+                Err(_) => {
+                    // Unplugged (ENODEV)
+                    keyboards.remove(idx);
+                    removed = true;
+                    continue;
+                }
+            };
+
+            for event in buf[..n].chunks_exact(event_size) {
+                let kind = u16::from_ne_bytes([event[time_size], event[time_size + 1]]);
+                let code = u16::from_ne_bytes([event[time_size + 2], event[time_size + 3]]);
+                let value = i32::from_ne_bytes([
+                    event[time_size + 4],
+                    event[time_size + 5],
+                    event[time_size + 6],
+                    event[time_size + 7],
+                ]);
+
+                // value is 1 for a press, 0 for a release and 2 for autorepeat
+                if kind != EV_KEY || value > 1 {
+                    continue;
+                }
+                if !callback(Input::Key(code as u32, value == 1)) {
+                    return Ok(());
+                }
+            }
+        }
+
+        if removed && !callback(Input::Connected(!keyboards.is_empty())) {
+            return Ok(());
+        }
+        if !callback(Input::Idle) {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
@@ -1,180 +1,702 @@
-use hyper::body::HttpBody as _;
-use hyper::http::Request;
+use hyper::http::{Method, Request};
 
-pub use crate::{AppState, GitHubNotification, PullRequest};
-use std::sync::{Arc, Mutex};
+use crate::actions::{ActionRequest, PrAction};
+use crate::clock::SharedClock;
+use crate::config::{Config, GitHubAccount};
+use crate::credentials::{CredentialStore, Token};
+use crate::http;
+use crate::scheduler::{DataSource, Scheduler, SourceKind};
+pub use crate::{AppState, CheckState, Notification, PrState, PullRequest, ReviewDecision};
+use std::sync::Arc;
 
-pub async fn run(data: Arc<Mutex<AppState>>) {
-    let _data = data.clone();
-    tokio::spawn(async move { pulls(_data.clone()).await });
+pub async fn register(scheduler: &Scheduler, config: &Config) {
+    let store = CredentialStore::open().await;
+    for account in &config.github_accounts {
+        let gh = match GitHub::connect(account.clone(), &store).await {
+            Ok(gh) => Arc::new(gh),
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
 
-    let pat = std::env::var("PAT").expect("must provide $PAT env var");
+        scheduler.add(Notifications {
+            gh: gh.clone(),
+            last_modified: None,
+            last: Vec::new(),
+        });
+        scheduler.add(Pulls {
+            gh,
+            window_days: config.submitted_window_days,
+            clock: scheduler.clock(),
+        });
+    }
+}
 
-    let auth = base64::encode(format!("colinwm:{pat}").into_bytes());
+pub struct GitHub {
+    account: GitHubAccount,
+    auth: String,
+    client: http::HttpsClient,
+}
 
-    let https = hyper_rustls::HttpsConnector::with_native_roots();
-    let client = hyper::Client::builder().build(https);
+impl GitHub {
+    pub async fn connect(account: GitHubAccount, store: &CredentialStore) -> Result<Self, String> {
+        let pat = Token::github(&account)
+            .get(store)
+            .await
+            .ok_or_else(|| format!("no GitHub token for {}", account.name))?;
+        Ok(Self::new(account, &pat, http::client()))
+    }
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(120));
-    loop {
-        let req = Request::builder()
-            .uri("https://api.github.com/notifications?participating=true&per_page=100")
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("Authorization", format!("Basic {auth}"))
-            .header("User-Agent", "colinwm")
-            .body(hyper::Body::empty())
-            .unwrap();
-        let mut response = client.request(req).await.unwrap();
-
-        let mut bytes: Vec<u8> = Vec::new();
-        while let Some(chunk) = response.body_mut().data().await {
-            bytes.extend(chunk.unwrap().as_ref());
+    // Point account.api_url at a mock server to run against recorded responses
+    pub fn new(account: GitHubAccount, pat: &str, client: http::HttpsClient) -> Self {
+        let auth = base64::encode(format!("{}:{pat}", account.user).into_bytes());
+        Self {
+            account,
+            auth,
+            client,
         }
+    }
 
-        let data_str = std::str::from_utf8(&bytes).expect("response was not utf8!");
-        let value: serde_json::Value =
-            serde_json::from_str(data_str).expect("response was not valid JSON!");
+    pub fn account(&self) -> &GitHubAccount {
+        &self.account
+    }
 
-        let mut notifications = Vec::new();
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        let uri = format!("{}{path}", self.account.api_url);
+        self.send(method, uri, body).await
+    }
 
-        if let serde_json::Value::Array(arr) = value {
-            for notification in arr {
-                if let serde_json::Value::String(reason) = &notification["reason"] {
-                    if reason == "state_change" || reason == "team_mention" || reason == "assign" {
-                        continue;
-                    }
-                }
+    async fn graphql(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        let body = serde_json::json!({ "query": query, "variables": variables });
+        let value = self
+            .send(Method::POST, self.account.graphql_url(), Some(body))
+            .await?;
 
-                let repository = notification["repository"]
-                    .get("name")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string();
+        if let Some(errors) = value.get("errors") {
+            return Err(errors[0]["message"]
+                .as_str()
+                .unwrap_or("unknown GraphQL error")
+                .to_string());
+        }
+        Ok(value["data"].clone())
+    }
 
-                let time = chrono::DateTime::parse_from_rfc3339(
-                    notification["updated_at"].as_str().unwrap(),
-                )
-                .unwrap()
-                .timestamp();
-
-                let action = notification["reason"].as_str().unwrap().to_string();
-                let url = notification["subject"]
-                    .get("url")
-                    .map(|v| v.as_str().unwrap_or(""))
-                    .unwrap_or("")
-                    .to_string();
-                let mut url = url.replace("api.github.com/repos", "github.com");
-
-                if url.contains("/pulls/") {
-                    url = url.replace("/pulls/", "/pull/");
-                }
+    async fn send(
+        &self,
+        method: Method,
+        uri: String,
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        let req = self.build(method, &uri, body)?;
+        http::send_json(&self.client, req).await
+    }
 
-                if url.is_empty() {
-                    continue;
-                }
+    fn build(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<Request<hyper::Body>, String> {
+        let body = match body {
+            Some(b) => hyper::Body::from(b.to_string()),
+            None => hyper::Body::empty(),
+        };
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("Authorization", format!("Basic {}", self.auth))
+            .header("User-Agent", &self.account.user)
+            .body(body)
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Notification lists longer than this many pages are cut short
+const MAX_PAGES: usize = 5;
 
-                notifications.push(GitHubNotification {
-                    title: notification["subject"]
-                        .get("title")
-                        .unwrap()
-                        .as_str()
-                        .unwrap_or("")
-                        .to_string(),
-                    action,
-                    repository,
-                    time,
-                    url,
-                });
+struct Notifications {
+    gh: Arc<GitHub>,
+    // Polls are conditional on the last response's Last-Modified. An unchanged list
+    // comes back as a 304, which doesn't count against the rate limit.
+    last_modified: Option<String>,
+    last: Vec<Notification>,
+}
+
+#[async_trait::async_trait]
+impl DataSource for Notifications {
+    type Output = Vec<Notification>;
+
+    fn name(&self) -> String {
+        format!("{}/notifications", self.gh.account.name)
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(120)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Notifications
+    }
+
+    async fn fetch(&mut self) -> Result<Self::Output, String> {
+        let mut uri = format!(
+            "{}/notifications?participating=true&per_page=100",
+            self.gh.account.api_url
+        );
+        let mut notifications = Vec::new();
+        let mut last_modified = None;
+
+        for page in 0..MAX_PAGES {
+            let mut req = self.gh.build(Method::GET, &uri, None)?;
+            if let (0, Some(since)) = (page, &self.last_modified) {
+                let since = since.parse().map_err(|_| "invalid Last-Modified")?;
+                req.headers_mut().insert("If-Modified-Since", since);
+            }
+
+            let response = http::send(&self.gh.client, req).await?;
+            if response.status == hyper::StatusCode::NOT_MODIFIED {
+                return Ok(self.last.clone());
+            }
+            if page == 0 {
+                last_modified = response.header("Last-Modified").map(str::to_string);
+            }
+
+            notifications.extend(parse_notifications(&response.value, &self.gh.account.name)?);
+            match response.next_page() {
+                Some(next) => uri = next,
+                None => break,
             }
         }
 
-        data.lock().unwrap().notifications = notifications;
+        // Only remembered once every page has arrived, so a failure part way
+        // through isn't answered with a 304 next time
+        self.last_modified = last_modified;
+        self.last = notifications.clone();
+        Ok(notifications)
+    }
 
-        interval.tick().await;
+    fn merge(&self, output: Self::Output, state: &mut AppState) {
+        state.set_notifications(&self.gh.account.name, output);
     }
 }
 
-pub async fn pulls(data: Arc<Mutex<AppState>>) {
-    let pat = std::env::var("PAT").expect("must provide $PAT env var");
-    let auth = base64::encode(format!("colinwm:{pat}").into_bytes());
-    let https = hyper_rustls::HttpsConnector::with_native_roots();
-    let client = hyper::Client::builder().build(https);
+fn parse_notifications(
+    value: &serde_json::Value,
+    account: &str,
+) -> Result<Vec<Notification>, String> {
+    let arr = value.as_array().ok_or("expected a list of notifications")?;
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(180));
-    loop {
-        let req = Request::builder()
-            .uri("https://api.github.com/search/issues?q=is:pr%20author:colinwm%20is:open")
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("Authorization", format!("Basic {auth}"))
-            .header("User-Agent", "colinwm")
-            .body(hyper::Body::empty())
-            .unwrap();
-        let mut response = client.request(req).await.unwrap();
-
-        let mut bytes: Vec<u8> = Vec::new();
-        while let Some(chunk) = response.body_mut().data().await {
-            bytes.extend(chunk.unwrap().as_ref());
+    let mut notifications = Vec::new();
+    for notification in arr {
+        let action = notification["reason"].as_str().unwrap_or("");
+        if action == "state_change" || action == "team_mention" || action == "assign" {
+            continue;
         }
 
-        let data_str = std::str::from_utf8(&bytes).expect("response was not utf8!");
-        let value: serde_json::Value =
-            serde_json::from_str(data_str).expect("response was not valid JSON!");
+        let repository = notification["repository"]["name"].as_str();
+        let time = notification["updated_at"]
+            .as_str()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok());
+        let url = html_url(notification);
 
-        let mut prs = Vec::new();
-        if let Some(serde_json::Value::Array(arr)) = value.get("items") {
-            for item in arr {
-                prs.push(extract_pr(item));
-            }
+        let (repository, time, url) = match (repository, time, url) {
+            (Some(r), Some(t), Some(u)) => (r, t, u),
+            _ => continue,
+        };
+
+        notifications.push(Notification {
+            title: notification["subject"]["title"]
+                .as_str()
+                .unwrap_or("")
+                .to_string(),
+            action: action.to_string(),
+            repository: repository.to_string(),
+            account: account.to_string(),
+            time: time.timestamp(),
+            url,
+        });
+    }
+
+    Ok(notifications)
+}
+
+// The subject URL points at the API (e.g. {repository.url}/pulls/12), so map it
+// onto the repository's HTML URL from the same response.
+fn html_url(notification: &serde_json::Value) -> Option<String> {
+    let repo_html = notification["repository"]["html_url"].as_str()?;
+    let repo_api = notification["repository"]["url"].as_str()?;
+    // Some subjects, like check suites, have no URL of their own
+    let subject = match notification["subject"]["url"].as_str() {
+        Some(s) => s,
+        None => return Some(repo_html.to_string()),
+    };
+
+    let rest = subject.strip_prefix(repo_api)?.trim_start_matches('/');
+    let (kind, id) = rest.split_once('/').unwrap_or((rest, ""));
+    let path = match kind {
+        "pulls" => format!("/pull/{id}"),
+        "issues" => format!("/issues/{id}"),
+        "commits" => format!("/commit/{id}"),
+        "releases" => "/releases".to_string(),
+        _ => String::new(),
+    };
+
+    Some(format!("{repo_html}{path}"))
+}
+
+const PULLS_QUERY: &str = "
+query($open: String!, $closed: String!, $review: String!) {
+  open: search(query: $open, type: ISSUE, first: 50) { nodes { ...pr } }
+  closed: search(query: $closed, type: ISSUE, first: 50) { nodes { ...pr } }
+  review: search(query: $review, type: ISSUE, first: 50) { nodes { ...pr } }
+}
+
+fragment pr on PullRequest {
+  id
+  number
+  title
+  url
+  updatedAt
+  closedAt
+  state
+  isDraft
+  reviewDecision
+  repository { name owner { login } }
+  comments { totalCount }
+  commits(last: 1) { nodes { commit { statusCheckRollup { state } } } }
+}
+";
+
+struct Pulls {
+    gh: Arc<GitHub>,
+    window_days: i64,
+    clock: SharedClock,
+}
+
+#[async_trait::async_trait]
+impl DataSource for Pulls {
+    type Output = PullLists;
+
+    fn name(&self) -> String {
+        format!("{}/pulls", self.gh.account.name)
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(180)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::PullRequests
+    }
+
+    async fn fetch(&mut self) -> Result<Self::Output, String> {
+        let user = &self.gh.account.user;
+        let since = self.clock.now() - chrono::Duration::days(self.window_days);
+        let variables = serde_json::json!({
+            "open": format!("is:pr author:{user} is:open"),
+            "closed": format!(
+                "is:pr author:{user} is:closed closed:>={}",
+                since.with_timezone(&chrono::Utc).format("%Y-%m-%d")
+            ),
+            "review": format!("is:pr review-requested:{user} is:open"),
+        });
+
+        let value = self.gh.graphql(PULLS_QUERY, variables).await?;
+        parse_pulls(&value, &self.gh.account.name)
+    }
+
+    fn merge(&self, output: Self::Output, state: &mut AppState) {
+        let (open, closed, review) = output;
+        let (drafts, open) = open.into_iter().partition(|pr| pr.state == PrState::Draft);
+        state.set_pulls(&self.gh.account.name, open, drafts, closed, review);
+    }
+}
+
+type PullLists = (Vec<PullRequest>, Vec<PullRequest>, Vec<PullRequest>);
+
+fn parse_pulls(value: &serde_json::Value, account: &str) -> Result<PullLists, String> {
+    let search = |name: &str| -> Result<Vec<PullRequest>, String> {
+        let nodes = value[name]["nodes"]
+            .as_array()
+            .ok_or_else(|| format!("missing {name} results"))?;
+        // One malformed result shouldn't hide the rest
+        let prs = nodes
+            .iter()
+            .filter_map(|node| match extract_pr(node, account) {
+                Ok(pr) => Some(pr),
+                Err(e) => {
+                    eprintln!("{account}: skipping {name} result: {e}");
+                    None
+                }
+            })
+            .collect();
+        Ok(prs)
+    };
+
+    Ok((search("open")?, search("closed")?, search("review")?))
+}
+
+fn extract_pr(node: &serde_json::Value, account: &str) -> Result<PullRequest, String> {
+    let updated_at = node["updatedAt"].as_str().ok_or("missing updatedAt")?;
+    let time = chrono::DateTime::parse_from_rfc3339(updated_at)
+        .map_err(|e| e.to_string())?
+        .timestamp();
+
+    let closed_at = match node["closedAt"].as_str() {
+        Some(t) => Some(
+            chrono::DateTime::parse_from_rfc3339(t)
+                .map_err(|e| e.to_string())?
+                .timestamp(),
+        ),
+        None => None,
+    };
+
+    let state = match node["state"].as_str() {
+        Some("MERGED") => PrState::Merged,
+        Some("CLOSED") => PrState::Closed,
+        _ if node["isDraft"].as_bool().unwrap_or(false) => PrState::Draft,
+        _ => PrState::Open,
+    };
+
+    Ok(PullRequest {
+        provider_id: node["id"].as_str().ok_or("missing id")?.to_string(),
+        title: node["title"].as_str().ok_or("missing title")?.to_string(),
+        url: node["url"].as_str().ok_or("missing url")?.to_string(),
+        updated_at: time,
+        repo_name: node["repository"]["name"]
+            .as_str()
+            .ok_or("missing repository")?
+            .to_string(),
+        owner: node["repository"]["owner"]["login"]
+            .as_str()
+            .ok_or("missing repository owner")?
+            .to_string(),
+        number: node["number"].as_u64().ok_or("missing number")?,
+        account: account.to_string(),
+        state,
+        closed_at,
+        checks: check_state(node),
+        review: review_decision(node),
+        comments: node["comments"]["totalCount"].as_u64().unwrap_or(0),
+    })
+}
+
+fn check_state(node: &serde_json::Value) -> Option<CheckState> {
+    match node["commits"]["nodes"][0]["commit"]["statusCheckRollup"]["state"].as_str()? {
+        "SUCCESS" => Some(CheckState::Success),
+        "FAILURE" | "ERROR" => Some(CheckState::Failure),
+        _ => Some(CheckState::Pending),
+    }
+}
+
+fn review_decision(node: &serde_json::Value) -> Option<ReviewDecision> {
+    match node["reviewDecision"].as_str()? {
+        "APPROVED" => Some(ReviewDecision::Approved),
+        "CHANGES_REQUESTED" => Some(ReviewDecision::ChangesRequested),
+        "REVIEW_REQUIRED" => Some(ReviewDecision::ReviewRequired),
+        _ => None,
+    }
+}
+
+const MERGE_STATUS_QUERY: &str = "
+query($owner: String!, $repo: String!, $number: Int!) {
+  repository(owner: $owner, name: $repo) {
+    pullRequest(number: $number) {
+      reviewDecision
+      commits(last: 1) { nodes { commit { statusCheckRollup { state } } } }
+    }
+  }
+}
+";
+
+pub async fn execute(gh: &GitHub, req: &ActionRequest) -> Result<String, String> {
+    let pull = format!("/repos/{}/{}/pulls/{}", req.owner, req.repo, req.number);
+    match req.action {
+        PrAction::Approve => {
+            let body = serde_json::json!({ "event": "APPROVE" });
+            gh.request(Method::POST, &format!("{pull}/reviews"), Some(body))
+                .await?;
+            Ok("approved".to_string())
         }
-        data.lock().unwrap().open_prs = prs;
+        PrAction::Merge => {
+            // Only merge approved PRs whose checks passed, as GitHub reports them now
+            let variables =
+                serde_json::json!({ "owner": req.owner, "repo": req.repo, "number": req.number });
+            let status = gh.graphql(MERGE_STATUS_QUERY, variables).await?;
+            let node = &status["repository"]["pullRequest"];
+            if review_decision(node) != Some(ReviewDecision::Approved) {
+                return Err("not approved".to_string());
+            }
+            if check_state(node) != Some(CheckState::Success) {
+                return Err("checks haven't passed".to_string());
+            }
 
-        let req = Request::builder()
-            .uri("https://api.github.com/search/issues?q=is:pr%20author:colinwm%20is:closed")
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("Authorization", format!("Basic {auth}"))
-            .header("User-Agent", "colinwm")
-            .body(hyper::Body::empty())
-            .unwrap();
-        let mut response = client.request(req).await.unwrap();
-
-        let mut bytes: Vec<u8> = Vec::new();
-        while let Some(chunk) = response.body_mut().data().await {
-            bytes.extend(chunk.unwrap().as_ref());
+            // Nor anything GitHub won't merge cleanly, e.g. with conflicts
+            let pr = gh.request(Method::GET, &pull, None).await?;
+            let state = pr["mergeable_state"].as_str().unwrap_or("unknown");
+            if state != "clean" {
+                return Err(format!("not mergeable ({state})"));
+            }
+
+            let body = serde_json::json!({ "merge_method": "squash" });
+            gh.request(Method::PUT, &format!("{pull}/merge"), Some(body))
+                .await?;
+            Ok("merged".to_string())
         }
+        PrAction::Rerun => {
+            let pr = gh.request(Method::GET, &pull, None).await?;
+            let sha = pr["head"]["sha"].as_str().unwrap_or("");
+            let runs = gh
+                .request(
+                    Method::GET,
+                    &format!(
+                        "/repos/{}/{}/actions/runs?head_sha={sha}",
+                        req.owner, req.repo
+                    ),
+                    None,
+                )
+                .await?;
 
-        let data_str = std::str::from_utf8(&bytes).expect("response was not utf8!");
-        let value: serde_json::Value =
-            serde_json::from_str(data_str).expect("response was not valid JSON!");
+            let mut rerun = 0;
+            if let Some(serde_json::Value::Array(arr)) = runs.get("workflow_runs") {
+                for run in arr {
+                    let conclusion = run["conclusion"].as_str().unwrap_or("");
+                    if conclusion != "failure" && conclusion != "timed_out" {
+                        continue;
+                    }
+                    let id = run["id"].as_u64().unwrap_or(0);
+                    gh.request(
+                        Method::POST,
+                        &format!(
+                            "/repos/{}/{}/actions/runs/{id}/rerun-failed-jobs",
+                            req.owner, req.repo
+                        ),
+                        None,
+                    )
+                    .await?;
+                    rerun += 1;
+                }
+            }
 
-        let mut prs = Vec::new();
-        if let Some(serde_json::Value::Array(arr)) = value.get("items") {
-            for item in arr {
-                prs.push(extract_pr(item));
+            if rerun == 0 {
+                return Err("no failed runs".to_string());
             }
+            Ok(format!("re-ran {rerun} workflow(s)"))
+        }
+        PrAction::Ready => {
+            gh.graphql(
+                "mutation($id: ID!) {
+                    markPullRequestReadyForReview(input: { pullRequestId: $id }) {
+                        pullRequest { isDraft }
+                    }
+                }",
+                serde_json::json!({ "id": req.provider_id }),
+            )
+            .await?;
+            Ok("ready for review".to_string())
+        }
+        PrAction::Close => {
+            let body = serde_json::json!({ "state": "closed" });
+            gh.request(Method::PATCH, &pull, Some(body)).await?;
+            Ok("closed".to_string())
         }
-        data.lock().unwrap().closed_prs = prs;
-
-        interval.tick().await;
     }
 }
 
-fn extract_pr(item: &serde_json::Value) -> PullRequest {
-    let time = chrono::DateTime::parse_from_rfc3339(item["updated_at"].as_str().unwrap())
-        .unwrap()
-        .timestamp();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_recorded_pulls() {
+        let response: serde_json::Value =
+            serde_json::from_str(include_str!("../fixtures/github/pulls.json")).unwrap();
+        let (open, closed, review) = parse_pulls(&response["data"], "work").unwrap();
+
+        assert_eq!(open.len(), 2);
+        let pr = &open[0];
+        assert_eq!(pr.provider_id, "PR_kwDOABC12");
+        assert_eq!(pr.number, 12);
+        assert_eq!(pr.owner, "colin353");
+        assert_eq!(pr.repo_name, "console-gui");
+        assert_eq!(pr.account, "work");
+        assert_eq!(pr.state, PrState::Open);
+        assert_eq!(pr.checks, Some(CheckState::Success));
+        assert_eq!(pr.review, Some(ReviewDecision::Approved));
+        assert_eq!(pr.comments, 3);
+        assert_eq!(pr.closed_at, None);
 
-    let url = item.get("html_url").unwrap().as_str().unwrap().to_string();
-    let re = regex::Regex::new("^https://github.com/([^/]+)/([^/]+)").unwrap();
-    let mut repo_name = String::new();
-    if let Some(cap) = re.captures_iter(&url).next() {
-        repo_name = cap[2].to_string();
+        let draft = &open[1];
+        assert_eq!(draft.state, PrState::Draft);
+        assert_eq!(draft.checks, Some(CheckState::Pending));
+        assert_eq!(draft.review, None);
+
+        assert_eq!(closed[0].state, PrState::Merged);
+        assert_eq!(closed[0].closed_at, Some(1714478400));
+        assert_eq!(closed[1].state, PrState::Closed);
+        assert_eq!(closed[1].checks, Some(CheckState::Failure));
+
+        let pr = &review[0];
+        assert_eq!(
+            (pr.owner.as_str(), pr.repo_name.as_str()),
+            ("someone", "dotfiles")
+        );
+        assert_eq!(pr.checks, None);
+        assert_eq!(pr.review, Some(ReviewDecision::ChangesRequested));
+        assert_eq!(pr.comments, 1);
     }
 
-    PullRequest {
-        title: item.get("title").unwrap().as_str().unwrap().to_string(),
-        url: item.get("html_url").unwrap().as_str().unwrap().to_string(),
-        updated_at: time,
-        repo_name,
+    #[test]
+    fn missing_search_is_an_error() {
+        let value = serde_json::json!({ "open": { "nodes": [] }, "closed": null });
+        assert_eq!(
+            parse_pulls(&value, "work").unwrap_err(),
+            "missing closed results"
+        );
+    }
+
+    // A client for a mock server answering from the named fixture
+    fn mock(fixture: &str) -> Arc<GitHub> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/github")
+            .join(fixture);
+        let (addr, server) = crate::mock_github::bind(&path, "127.0.0.1:0").unwrap();
+        tokio::spawn(server);
+
+        let account = GitHubAccount {
+            name: "work".to_string(),
+            api_url: format!("http://{addr}"),
+            graphql_url: None,
+            user: "colinwm".to_string(),
+            token_env: "GITHUB_TOKEN".to_string(),
+        };
+        Arc::new(GitHub::new(account, "token", http::client()))
+    }
+
+    fn notifications(gh: &Arc<GitHub>) -> Notifications {
+        Notifications {
+            gh: gh.clone(),
+            last_modified: None,
+            last: Vec::new(),
+        }
+    }
+
+    fn pulls(gh: &Arc<GitHub>) -> Pulls {
+        Pulls {
+            gh: gh.clone(),
+            window_days: 14,
+            clock: Arc::new(crate::clock::FixedClock::at(1714557600)),
+        }
+    }
+
+    fn titles(notifications: &[Notification]) -> Vec<&str> {
+        notifications.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_notifications() {
+        let gh = mock("ok.json");
+        let mut source = notifications(&gh);
+
+        let fetched = source.fetch().await.unwrap();
+        // State changes, team mentions and assignments are left out, as is the
+        // mention with no timestamp. The last one is from the second page.
+        assert_eq!(
+            titles(&fetched),
+            [
+                "Add a layout engine",
+                "CI failed on main",
+                "Crash on startup"
+            ]
+        );
+        assert_eq!(fetched[0].action, "review_requested");
+        assert_eq!(fetched[0].repository, "console-gui");
+        assert_eq!(fetched[0].account, "work");
+        assert_eq!(fetched[0].time, 1714554000);
+        assert_eq!(
+            fetched[0].url,
+            "https://github.com/colin353/console-gui/pull/12"
+        );
+        // Check suites have no URL of their own, so they link to the repo
+        assert_eq!(fetched[1].url, "https://github.com/colin353/console-gui");
+        assert_eq!(
+            fetched[2].url,
+            "https://github.com/colin353/console-gui/issues/7"
+        );
+        assert_eq!(
+            source.last_modified.as_deref(),
+            Some("Wed, 01 May 2024 09:00:00 GMT")
+        );
+
+        // The list hasn't changed, so the server answers 304 and the last one is reused
+        let refetched = source.fetch().await.unwrap();
+        assert_eq!(titles(&refetched), titles(&fetched));
+    }
+
+    #[tokio::test]
+    async fn fetches_pulls() {
+        let gh = mock("ok.json");
+        let (open, closed, review) = pulls(&gh).fetch().await.unwrap();
+
+        // The node without a number is skipped
+        let numbers = |prs: &[PullRequest]| prs.iter().map(|pr| pr.number).collect::<Vec<_>>();
+        assert_eq!(numbers(&open), [12, 13]);
+        assert_eq!(open[1].state, PrState::Draft);
+        assert_eq!(numbers(&closed), [10]);
+        assert_eq!(closed[0].state, PrState::Merged);
+        assert_eq!(numbers(&review), [14]);
+        assert_eq!(review[0].checks, Some(CheckState::Failure));
+        assert_eq!(review[0].review, Some(ReviewDecision::ChangesRequested));
+    }
+
+    #[tokio::test]
+    async fn unauthorized() {
+        let gh = mock("unauthorized.json");
+        let expected = "401 Bad credentials (is the token still valid?)";
+        assert_eq!(notifications(&gh).fetch().await.unwrap_err(), expected);
+        assert_eq!(pulls(&gh).fetch().await.unwrap_err(), expected);
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let gh = mock("rate_limited.json");
+        // The reset time is shown in local time, which depends on where this runs
+        let e = notifications(&gh).fetch().await.unwrap_err();
+        assert!(e.starts_with("rate limited until "), "{e}");
+        assert_eq!(
+            pulls(&gh).fetch().await.unwrap_err(),
+            "rate limited, retry in 60s"
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_responses() {
+        let gh = mock("malformed.json");
+
+        let mut source = notifications(&gh);
+        assert_eq!(source.fetch().await.unwrap_err(), "502 Bad Gateway");
+        let e = source.fetch().await.unwrap_err();
+        assert!(e.starts_with("invalid JSON from "), "{e}");
+        assert_eq!(
+            source.fetch().await.unwrap_err(),
+            "expected a list of notifications"
+        );
+        // Nothing was remembered from the failed polls
+        assert!(source.last_modified.is_none());
+
+        let mut source = pulls(&gh);
+        assert_eq!(source.fetch().await.unwrap_err(), "missing open results");
+        assert_eq!(source.fetch().await.unwrap_err(), "API rate limit exceeded");
     }
 }
@@ -0,0 +1,519 @@
+use hyper::http::{Method, Request};
+
+use crate::actions::{ActionRequest, PrAction};
+use crate::clock::SharedClock;
+use crate::config::{Config, GitLabAccount};
+use crate::credentials::{CredentialStore, Token};
+use crate::http;
+use crate::scheduler::{DataSource, Scheduler, SourceKind};
+pub use crate::{AppState, Notification, PrState, PullRequest};
+use std::sync::Arc;
+
+pub async fn register(scheduler: &Scheduler, config: &Config) {
+    let store = CredentialStore::open().await;
+    for account in &config.gitlab_accounts {
+        let gl = match GitLab::connect(account.clone(), &store).await {
+            Ok(gl) => Arc::new(gl),
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+
+        scheduler.add(Todos { gl: gl.clone() });
+        scheduler.add(MergeRequests {
+            gl,
+            window_days: config.submitted_window_days,
+            clock: scheduler.clock(),
+        });
+    }
+}
+
+pub struct GitLab {
+    account: GitLabAccount,
+    token: String,
+    client: http::HttpsClient,
+}
+
+impl GitLab {
+    pub async fn connect(account: GitLabAccount, store: &CredentialStore) -> Result<Self, String> {
+        let token = Token::gitlab(&account)
+            .get(store)
+            .await
+            .ok_or_else(|| format!("no GitLab token for {}", account.name))?;
+        Ok(Self::new(account, &token, http::client()))
+    }
+
+    // Point account.url at a mock server to run against recorded responses
+    pub fn new(account: GitLabAccount, token: &str, client: http::HttpsClient) -> Self {
+        Self {
+            account,
+            token: token.to_string(),
+            client,
+        }
+    }
+
+    pub fn account(&self) -> &GitLabAccount {
+        &self.account
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        let body = match body {
+            Some(b) => hyper::Body::from(b.to_string()),
+            None => hyper::Body::empty(),
+        };
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{}/api/v4{path}", self.account.url))
+            .header("Content-Type", "application/json")
+            .header("PRIVATE-TOKEN", &self.token)
+            .body(body)
+            .map_err(|e| e.to_string())?;
+        http::send_json(&self.client, req).await
+    }
+}
+
+struct Todos {
+    gl: Arc<GitLab>,
+}
+
+#[async_trait::async_trait]
+impl DataSource for Todos {
+    type Output = Vec<Notification>;
+
+    fn name(&self) -> String {
+        format!("{}/todos", self.gl.account.name)
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(120)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Notifications
+    }
+
+    async fn fetch(&mut self) -> Result<Self::Output, String> {
+        let value = self
+            .gl
+            .request(Method::GET, "/todos?state=pending&per_page=100", None)
+            .await?;
+        Ok(parse_todos(&value, &self.gl.account.name))
+    }
+
+    fn merge(&self, output: Self::Output, state: &mut AppState) {
+        state.set_notifications(&self.gl.account.name, output);
+    }
+}
+
+fn parse_todos(value: &serde_json::Value, account: &str) -> Vec<Notification> {
+    let mut notifications = Vec::new();
+
+    if let serde_json::Value::Array(arr) = value {
+        for todo in arr {
+            let time = todo["updated_at"]
+                .as_str()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok());
+            let url = todo["target_url"].as_str();
+
+            let (time, url) = match (time, url) {
+                (Some(t), Some(u)) => (t, u),
+                _ => continue,
+            };
+
+            let title = todo["target"]["title"]
+                .as_str()
+                .or_else(|| todo["body"].as_str())
+                .unwrap_or("");
+
+            notifications.push(Notification {
+                title: title.to_string(),
+                action: todo["action_name"].as_str().unwrap_or("").to_string(),
+                repository: todo["project"]["name"].as_str().unwrap_or("").to_string(),
+                account: account.to_string(),
+                time: time.timestamp(),
+                url: url.to_string(),
+            });
+        }
+    }
+
+    notifications
+}
+
+struct MergeRequests {
+    gl: Arc<GitLab>,
+    window_days: i64,
+    clock: SharedClock,
+}
+
+impl MergeRequests {
+    async fn list(&self, query: &str) -> Result<Vec<PullRequest>, String> {
+        let value = self.gl.request(Method::GET, query, None).await?;
+        parse_merge_requests(&value, &self.gl.account.name)
+    }
+}
+
+// Open, draft, recently closed and review-requested merge requests
+type MergeRequestLists = (
+    Vec<PullRequest>,
+    Vec<PullRequest>,
+    Vec<PullRequest>,
+    Vec<PullRequest>,
+);
+
+#[async_trait::async_trait]
+impl DataSource for MergeRequests {
+    type Output = MergeRequestLists;
+
+    fn name(&self) -> String {
+        format!("{}/merge_requests", self.gl.account.name)
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(180)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::PullRequests
+    }
+
+    async fn fetch(&mut self) -> Result<Self::Output, String> {
+        let user = &self.gl.account.user;
+        let since = self.clock.now() - chrono::Duration::days(self.window_days);
+
+        let authored = self
+            .list("/merge_requests?scope=created_by_me&state=opened&per_page=100")
+            .await?;
+        let assigned = self
+            .list("/merge_requests?scope=assigned_to_me&state=opened&per_page=100")
+            .await?;
+        let recent = self
+            .list(&format!(
+                "/merge_requests?scope=created_by_me&updated_after={}&per_page=100",
+                since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ))
+            .await?;
+        let review = self
+            .list(&format!(
+                "/merge_requests?scope=all&reviewer_username={user}&state=opened&per_page=100"
+            ))
+            .await?;
+
+        // Assigned MRs are usually also authored ones, so drop duplicates
+        let mut open = authored;
+        for mr in assigned {
+            if !open.iter().any(|o| o.url == mr.url) {
+                open.push(mr);
+            }
+        }
+        let (drafts, open) = open.into_iter().partition(|mr| mr.state == PrState::Draft);
+
+        let closed = recent
+            .into_iter()
+            .filter(|mr| matches!(mr.closed_at, Some(t) if t >= since.timestamp()))
+            .collect();
+
+        Ok((open, drafts, closed, review))
+    }
+
+    fn merge(&self, output: Self::Output, state: &mut AppState) {
+        let (open, drafts, closed, review) = output;
+        state.set_pulls(&self.gl.account.name, open, drafts, closed, review);
+    }
+}
+
+fn parse_merge_requests(
+    value: &serde_json::Value,
+    account: &str,
+) -> Result<Vec<PullRequest>, String> {
+    let mrs = value
+        .as_array()
+        .ok_or("expected a list of merge requests")?;
+    // One malformed merge request shouldn't hide the rest
+    Ok(mrs
+        .iter()
+        .filter_map(|mr| match extract_mr(mr, account) {
+            Ok(mr) => Some(mr),
+            Err(e) => {
+                eprintln!("{account}: skipping merge request: {e}");
+                None
+            }
+        })
+        .collect())
+}
+
+fn extract_mr(mr: &serde_json::Value, account: &str) -> Result<PullRequest, String> {
+    let timestamp = |field: &str| -> Option<i64> {
+        mr[field]
+            .as_str()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.timestamp())
+    };
+
+    let updated_at = timestamp("updated_at").ok_or("missing updated_at")?;
+    let state = match mr["state"].as_str() {
+        Some("merged") => PrState::Merged,
+        Some("closed") | Some("locked") => PrState::Closed,
+        _ if mr["draft"].as_bool().unwrap_or(false) => PrState::Draft,
+        _ => PrState::Open,
+    };
+    let closed_at = match state {
+        PrState::Merged => timestamp("merged_at"),
+        PrState::Closed => timestamp("closed_at"),
+        PrState::Open | PrState::Draft => None,
+    };
+
+    // references.full looks like group/subgroup/project!12
+    let reference = mr["references"]["full"].as_str().unwrap_or("");
+    let path = reference.split('!').next().unwrap_or("");
+    let (owner, repo_name) = path.rsplit_once('/').unwrap_or(("", path));
+
+    Ok(PullRequest {
+        provider_id: mr["project_id"]
+            .as_u64()
+            .ok_or("missing project_id")?
+            .to_string(),
+        title: mr["title"].as_str().ok_or("missing title")?.to_string(),
+        url: mr["web_url"].as_str().ok_or("missing web_url")?.to_string(),
+        updated_at,
+        repo_name: repo_name.to_string(),
+        owner: owner.to_string(),
+        number: mr["iid"].as_u64().ok_or("missing iid")?,
+        account: account.to_string(),
+        state,
+        closed_at,
+        // Pipeline and approval state aren't part of the list API
+        checks: None,
+        review: None,
+        comments: mr["user_notes_count"].as_u64().unwrap_or(0),
+    })
+}
+
+pub async fn execute(gl: &GitLab, req: &ActionRequest) -> Result<String, String> {
+    let mr = format!(
+        "/projects/{}/merge_requests/{}",
+        req.provider_id, req.number
+    );
+    match req.action {
+        PrAction::Approve => {
+            gl.request(Method::POST, &format!("{mr}/approve"), None)
+                .await?;
+            Ok("approved".to_string())
+        }
+        PrAction::Merge => {
+            let details = gl.request(Method::GET, &mr, None).await?;
+            let status = details["detailed_merge_status"]
+                .as_str()
+                .unwrap_or("unknown");
+            if status != "mergeable" {
+                return Err(format!("not mergeable ({status})"));
+            }
+
+            let body = serde_json::json!({ "squash": true });
+            gl.request(Method::PUT, &format!("{mr}/merge"), Some(body))
+                .await?;
+            Ok("merged".to_string())
+        }
+        PrAction::Rerun => {
+            let details = gl.request(Method::GET, &mr, None).await?;
+            let pipeline = &details["head_pipeline"];
+            if pipeline["status"].as_str() != Some("failed") {
+                return Err("no failed pipeline".to_string());
+            }
+
+            let id = pipeline["id"].as_u64().unwrap_or(0);
+            gl.request(
+                Method::POST,
+                &format!("/projects/{}/pipelines/{id}/retry", req.provider_id),
+                None,
+            )
+            .await?;
+            Ok("retried pipeline".to_string())
+        }
+        PrAction::Ready => {
+            // GitLab tracks draft status through a title prefix
+            let details = gl.request(Method::GET, &mr, None).await?;
+            let title = details["title"].as_str().unwrap_or("");
+            let mut ready = title;
+            for prefix in ["Draft:", "[Draft]", "(Draft)", "WIP:", "[WIP]"] {
+                if let Some(rest) = ready.strip_prefix(prefix) {
+                    ready = rest.trim_start();
+                }
+            }
+
+            let body = serde_json::json!({ "title": ready });
+            gl.request(Method::PUT, &mr, Some(body)).await?;
+            Ok("ready for review".to_string())
+        }
+        PrAction::Close => {
+            let body = serde_json::json!({ "state_event": "close" });
+            gl.request(Method::PUT, &mr, Some(body)).await?;
+            Ok("closed".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(contents: &str) -> serde_json::Value {
+        serde_json::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn parses_todos() {
+        let todos = parse_todos(
+            &fixture(include_str!("../fixtures/gitlab/todos.json")),
+            "oss",
+        );
+
+        // The todo without updated_at is dropped
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[0].title, "Speed up the build");
+        assert_eq!(todos[0].action, "review_requested");
+        assert_eq!(todos[0].repository, "builder");
+        assert_eq!(todos[0].account, "oss");
+        assert_eq!(todos[0].time, 1714555800);
+        // Falls back to the body when there's no target
+        assert_eq!(todos[1].title, "@colin can you look at this?");
+        assert_eq!(todos[1].time, 1714543200);
+    }
+
+    #[test]
+    fn parses_merge_requests() {
+        let value = fixture(include_str!("../fixtures/gitlab/merge_requests.json"));
+        let mrs = parse_merge_requests(&value, "oss").unwrap();
+
+        // The one without a title is skipped, not the whole list
+        assert_eq!(mrs.len(), 4);
+
+        let mr = &mrs[0];
+        assert_eq!(mr.state, PrState::Open);
+        assert_eq!(mr.provider_id, "42");
+        assert_eq!(mr.number, 7);
+        assert_eq!(mr.owner, "infra/tools");
+        assert_eq!(mr.repo_name, "builder");
+        assert_eq!(mr.comments, 4);
+        assert_eq!(mr.closed_at, None);
+
+        assert_eq!(mrs[1].state, PrState::Draft);
+
+        assert_eq!(mrs[2].state, PrState::Merged);
+        assert_eq!(mrs[2].closed_at, Some(1714478400));
+
+        // Closed wins over draft
+        let mr = &mrs[3];
+        assert_eq!(mr.state, PrState::Closed);
+        assert_eq!(mr.closed_at, Some(1714392000));
+        assert_eq!(
+            (mr.owner.as_str(), mr.repo_name.as_str()),
+            ("colin", "dotfiles")
+        );
+        assert_eq!(mr.comments, 0);
+    }
+
+    #[test]
+    fn reference_without_a_group() {
+        let mr = serde_json::json!({
+            "project_id": 1,
+            "iid": 2,
+            "title": "t",
+            "state": "opened",
+            "web_url": "https://gitlab.example.com/p/-/merge_requests/2",
+            "updated_at": "2024-05-01T00:00:00Z",
+            "references": { "full": "project!2" },
+        });
+        let mr = extract_mr(&mr, "oss").unwrap();
+        assert_eq!((mr.owner.as_str(), mr.repo_name.as_str()), ("", "project"));
+    }
+
+    #[test]
+    fn merge_requests_must_be_a_list() {
+        let value = serde_json::json!({ "message": "403 Forbidden" });
+        assert!(parse_merge_requests(&value, "oss").is_err());
+    }
+
+    // A client for a mock server answering from the named fixture
+    fn mock(fixture: &str) -> Arc<GitLab> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/gitlab")
+            .join(fixture);
+        let (addr, server) = crate::mock_github::bind(&path, "127.0.0.1:0").unwrap();
+        tokio::spawn(server);
+
+        let account = GitLabAccount {
+            name: "oss".to_string(),
+            url: format!("http://{addr}"),
+            user: "colin".to_string(),
+            token_env: "GITLAB_TOKEN".to_string(),
+        };
+        Arc::new(GitLab::new(account, "token", http::client()))
+    }
+
+    fn merge_requests(gl: &Arc<GitLab>) -> MergeRequests {
+        MergeRequests {
+            gl: gl.clone(),
+            window_days: 14,
+            clock: Arc::new(crate::clock::FixedClock::at(1714557600)),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_todos() {
+        let gl = mock("ok.json");
+        let todos = Todos { gl }.fetch().await.unwrap();
+
+        let titles: Vec<&str> = todos.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["Add arm64 builds", "Flaky cache test"]);
+        assert_eq!(todos[0].action, "review_requested");
+        assert_eq!(todos[0].repository, "images");
+        assert_eq!(todos[0].account, "oss");
+    }
+
+    #[tokio::test]
+    async fn fetches_merge_requests() {
+        let gl = mock("ok.json");
+        let (open, drafts, closed, review) = merge_requests(&gl).fetch().await.unwrap();
+
+        let numbers = |mrs: &[PullRequest]| mrs.iter().map(|mr| mr.number).collect::<Vec<_>>();
+        // 7 is both authored and assigned, and only shows up once
+        assert_eq!(numbers(&open), [7, 9]);
+        assert_eq!(numbers(&drafts), [8]);
+        // 3 was updated within the window but closed before it, and open ones
+        // from the same query are left out
+        assert_eq!(numbers(&closed), [5]);
+        assert_eq!(closed[0].state, PrState::Merged);
+        assert_eq!(numbers(&review), [11]);
+        assert_eq!(review[0].provider_id, "43");
+    }
+
+    #[tokio::test]
+    async fn unauthorized() {
+        let gl = mock("unauthorized.json");
+        let expected = "401 Unauthorized (is the token still valid?)";
+        assert_eq!(
+            Todos { gl: gl.clone() }.fetch().await.unwrap_err(),
+            expected
+        );
+        assert_eq!(merge_requests(&gl).fetch().await.unwrap_err(), expected);
+    }
+
+    #[tokio::test]
+    async fn not_found() {
+        let gl = mock("not_found.json");
+        assert_eq!(
+            Todos { gl: gl.clone() }.fetch().await.unwrap_err(),
+            "404 Not Found"
+        );
+        assert_eq!(
+            merge_requests(&gl).fetch().await.unwrap_err(),
+            "404 Not Found"
+        );
+    }
+}
@@ -1,16 +1,21 @@
+use serde::{Deserialize, Serialize};
+
 use crate::keyboard;
+use crate::scheduler::SourceKind;
 use crate::style;
-use crate::{command, AppState, CalendarEvent, Command, PageState};
+use crate::{command, AppState, CalendarEvent, Command, ListCursor, PageState};
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct HomeState {
-    slider: usize,
-    selected: usize,
-    scroll: usize,
+    cursor: ListCursor,
 }
 
 impl AppState {
     pub fn handle_kbd_home(&mut self, key: keyboard::Key) {
+        // Desktop notifications are listed above GitHub ones
+        let desktop = self.desktop_notifications.len();
+        let count = desktop + self.notifications.len();
         let mut hs = match &mut self.page {
             PageState::Home(hs) => hs,
             _ => unreachable!("wrong page!"),
@@ -38,42 +43,42 @@ impl AppState {
                 // Shortcuts
                 self.page = PageState::Shortcuts { selected: None };
             }
-            keyboard::Key::Slider(pos) => {
-                hs.slider = 5 - pos;
-
-                if hs.slider == 0 {
-                    if hs.scroll > 0 {
-                        hs.scroll -= 1;
-                    } else {
-                        hs.selected = 0;
-                    }
-                } else if hs.slider < 5 {
-                    hs.selected = hs.slider;
-                } else if hs.slider == 5 {
-                    hs.selected = 5;
-
-                    if hs.scroll >= self.notifications.len() - 5
-                        && hs.selected < self.notifications.len()
-                    {
-                        hs.selected += 1;
-                    }
-
-                    if hs.scroll >= self.notifications.len() - 5 {
-                        hs.scroll += 1;
-                    }
-                }
+            keyboard::Key::Slider(_) => {
+                hs.cursor
+                    .moved(&self.slider, count, self.layout.list_rows())
             }
             keyboard::Key::Execute => {
                 // Execute selected thingy
-                let selected = hs.selected + hs.scroll;
-                if let Some(item) = self.notifications.get(selected) {
+                let selected = hs.cursor.index();
+                if selected < desktop {
+                    self.close_desktop_notification(selected, true);
+                } else if let Some(item) = self.notifications.get(selected - desktop) {
                     command::open_url(&item.url);
                 }
             }
+            keyboard::Key::Abort => {
+                let selected = hs.cursor.index();
+                if selected < desktop {
+                    self.close_desktop_notification(selected, false);
+                }
+            }
             _ => (),
         }
     }
 
+    pub fn bind_home(&mut self, event: keyboard::KeyEvent) -> bool {
+        match event {
+            keyboard::KeyEvent::LongPress(keyboard::Key::Abort) => {
+                // Dismiss every desktop notification
+                while !self.desktop_notifications.is_empty() {
+                    self.close_desktop_notification(0, false);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn commands_home(&self) -> Vec<Command> {
         let mut commands = vec![
             Command::new("JOIN"),
@@ -93,21 +98,15 @@ impl AppState {
         commands
     }
 
-    pub fn heartbeat_home(&mut self) {
+    pub fn heartbeat_home(&mut self, now: std::time::Instant) {
+        let count = self.desktop_notifications.len() + self.notifications.len();
         let mut hs = match &mut self.page {
             PageState::Home(hs) => hs,
             _ => unreachable!("wrong page!"),
         };
 
-        if hs.slider == 0 {
-            if hs.scroll > 0 {
-                hs.scroll -= 1;
-            } else if hs.selected > 0 {
-                hs.selected -= 1;
-            }
-        } else if hs.slider == 5 {
-            hs.scroll += 1;
-        }
+        hs.cursor
+            .tick(&self.slider, count, self.layout.list_rows(), now);
     }
 
     pub fn render_home(&self, ui: &mut egui::Ui) {
@@ -117,31 +116,35 @@ impl AppState {
         };
 
         let mut frame = egui::Frame::none();
-        frame.margin = egui::Vec2::new(20.0, 20.0);
+        frame.margin = egui::Vec2::splat(self.layout.page_margin());
         frame.show(ui, |ui| {
-            let clip_rect = ui.max_rect().expand(5.0);
+            let clip_rect = ui.max_rect().expand(self.layout.margin());
             ui.set_clip_rect(clip_rect);
             if let Some(calendar_event) = self.calendar.as_ref() {
                 ui.horizontal(|ui| {
                     let mut frame = egui::Frame::none();
-                    frame.margin = egui::Vec2::new(5.0, 5.0);
+                    frame.margin = egui::Vec2::splat(self.layout.margin());
                     frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
                     frame.show(ui, |ui| {
                         let desc = egui::Label::new(
-                            egui::RichText::new(style::eta(calendar_event.start)).monospace(),
+                            egui::RichText::new(style::eta(
+                                calendar_event.start,
+                                self.clock.timestamp(),
+                            ))
+                            .monospace(),
                         );
                         ui.add(desc);
                     });
 
-                    ui.add_space(20.0);
+                    ui.add_space(2.0 * self.layout.gap());
                     ui.heading(&calendar_event.title);
                 });
 
-                ui.add_space(10.0);
+                ui.add_space(self.layout.gap());
 
                 ui.horizontal(|ui| {
                     let mut frame = egui::Frame::none();
-                    frame.margin = egui::Vec2::new(5.0, 5.0);
+                    frame.margin = egui::Vec2::splat(self.layout.margin());
 
                     if let Some(_zoom_url) = calendar_event.zoom_url.as_ref() {
                         frame = frame.stroke(egui::Stroke::new(style::STROKE, style::ZOOM_COLOR));
@@ -163,53 +166,137 @@ impl AppState {
                         });
                     }
 
-                    ui.add_space(20.0);
+                    ui.add_space(2.0 * self.layout.gap());
                     ui.heading(&calendar_event.time);
                 });
 
-                ui.add_space(40.0);
+                ui.add_space(self.layout.margin());
+                self.freshness(ui, SourceKind::Calendar);
+                ui.add_space(self.layout.px(35.0));
+            } else {
+                self.freshness(ui, SourceKind::Calendar);
+                ui.add_space(self.layout.gap());
             }
 
-            for (idx, notification) in self.notifications.iter().skip(hs.scroll).enumerate() {
-                ui.horizontal(|ui| {
-                    let mut frame = egui::Frame::none();
-                    frame.margin = egui::Vec2::new(5.0, 5.0);
-                    frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
-                    if idx == hs.selected {
-                        frame.fill = style::FG;
-                    }
+            let desktop = self.desktop_notifications.len();
+            let rows = self.layout.list_rows();
+            for (idx, notification) in self
+                .desktop_notifications
+                .iter()
+                .enumerate()
+                .skip(hs.cursor.scroll)
+                .take(rows)
+            {
+                let body = notification.body.lines().next().unwrap_or("");
+                self.render_notification(
+                    ui,
+                    idx - hs.cursor.scroll == hs.cursor.selected,
+                    notification.time,
+                    &notification.summary,
+                    format!("{} from {}", body, notification.app),
+                );
+            }
 
-                    frame.show(ui, |ui| {
-                        let desc = egui::Label::new(
-                            egui::RichText::new(style::eta(notification.time))
-                                .monospace()
-                                .color(if idx == hs.selected {
-                                    style::BG
-                                } else {
-                                    style::FG
-                                }),
-                        );
-                        ui.add(desc);
-                    });
+            self.freshness(ui, SourceKind::Notifications);
+            ui.add_space(self.layout.margin());
 
-                    ui.add_space(10.0);
-                    ui.add(egui::Label::new(
-                        egui::RichText::new(&notification.title).heading(),
-                    ));
-                });
-                ui.horizontal(|ui| {
-                    ui.add(egui::Label::new(
-                        egui::RichText::new(format!(
-                            "{} in {}",
-                            notification.action, notification.repository
-                        ))
-                        .color(style::FG_MUTED)
-                        .heading(),
-                    ));
-                });
+            for (idx, notification) in self
+                .notifications
+                .iter()
+                .enumerate()
+                .skip(hs.cursor.scroll.saturating_sub(desktop))
+                .take(rows.saturating_sub(desktop.saturating_sub(hs.cursor.scroll)))
+            {
+                self.render_notification(
+                    ui,
+                    desktop + idx - hs.cursor.scroll == hs.cursor.selected,
+                    notification.time,
+                    &notification.title,
+                    format!(
+                        "{}{} in {}",
+                        self.account_tag(&notification.account),
+                        notification.action,
+                        notification.repository
+                    ),
+                );
+            }
+        });
+    }
 
-                ui.add_space(10.0);
+    fn render_notification(
+        &self,
+        ui: &mut egui::Ui,
+        selected: bool,
+        time: i64,
+        title: &str,
+        detail: String,
+    ) {
+        ui.horizontal(|ui| {
+            let mut frame = egui::Frame::none();
+            frame.margin = egui::Vec2::splat(self.layout.margin());
+            frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
+            if selected {
+                frame.fill = style::FG;
             }
+
+            frame.show(ui, |ui| {
+                let desc = egui::Label::new(
+                    egui::RichText::new(style::eta(time, self.clock.timestamp()))
+                        .monospace()
+                        .color(if selected { style::BG } else { style::FG }),
+                );
+                ui.add(desc);
+            });
+
+            ui.add_space(self.layout.gap());
+            ui.add(egui::Label::new(egui::RichText::new(title).heading()));
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::Label::new(
+                egui::RichText::new(detail).color(style::FG_MUTED).heading(),
+            ));
         });
+
+        ui.add_space(self.layout.gap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_recording() {
+        let mut state = crate::recording::fixture_state(PageState::home());
+        crate::recording::replay_fixture("home.jsonl", &mut state);
+        let hs = match &state.page {
+            PageState::Home(hs) => hs,
+            _ => panic!("left the home page"),
+        };
+        // Down to the bottom of the slider, which scrolls the last of the four
+        // into view, and back up to the middle row
+        assert_eq!(hs.cursor.scroll, 1);
+        assert_eq!(hs.cursor.index(), 2);
+        assert_eq!(state.notifications[hs.cursor.index()].title, "Mock server");
+    }
+
+    #[test]
+    fn replay_scrolls_while_the_slider_rests_at_the_bottom() {
+        let mut state = crate::recording::fixture_state(PageState::home());
+        // Plenty to scroll through
+        state.notifications = state
+            .notifications
+            .iter()
+            .cycle()
+            .take(40)
+            .cloned()
+            .collect();
+        crate::recording::replay_fixture("scroll.jsonl", &mut state);
+        let hs = match &state.page {
+            PageState::Home(hs) => hs,
+            _ => panic!("left the home page"),
+        };
+        // A row per heartbeat for the first second at the bottom, then two
+        assert_eq!(hs.cursor.scroll, 5 + 2 * 5);
     }
 }
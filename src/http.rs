@@ -0,0 +1,118 @@
+use hyper::body::HttpBody as _;
+use hyper::StatusCode;
+
+pub type HttpsClient = hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+// Plain http URLs work too, e.g. for pointing an account at a local mock server
+pub fn client() -> HttpsClient {
+    let https = hyper_rustls::HttpsConnector::with_native_roots();
+    hyper::Client::builder().build(https)
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: hyper::HeaderMap,
+    // Null when the body is empty, e.g. for 304 Not Modified
+    pub value: serde_json::Value,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    // The rel="next" URL from a Link header, for paginated lists
+    pub fn next_page(&self) -> Option<String> {
+        self.header("Link")?.split(',').find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            params
+                .split(';')
+                .any(|p| p.trim() == "rel=\"next\"")
+                .then(|| url.trim().trim_start_matches('<').trim_end_matches('>'))
+                .map(str::to_string)
+        })
+    }
+}
+
+// Sends a request and decodes the JSON response. Statuses other than 2xx and 304 Not
+// Modified become errors.
+pub async fn send(
+    client: &HttpsClient,
+    req: hyper::Request<hyper::Body>,
+) -> Result<Response, String> {
+    let uri = req.uri().clone();
+    let mut response = client.request(req).await.map_err(|e| e.to_string())?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        bytes.extend(chunk.map_err(|e| e.to_string())?.as_ref());
+    }
+
+    let status = response.status();
+    // Error pages from proxies and load balancers often aren't JSON
+    let value = if bytes.is_empty() {
+        Ok(serde_json::Value::Null)
+    } else {
+        serde_json::from_slice(&bytes)
+    };
+    let valid = value.is_ok();
+    let response = Response {
+        status,
+        headers: response.headers().clone(),
+        value: value.unwrap_or_default(),
+    };
+
+    if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+        return Err(error(&response));
+    }
+    if !valid {
+        return Err(format!("invalid JSON from {uri}"));
+    }
+    Ok(response)
+}
+
+// Like send, but for callers that only want the body
+pub async fn send_json(
+    client: &HttpsClient,
+    req: hyper::Request<hyper::Body>,
+) -> Result<serde_json::Value, String> {
+    send(client, req).await.map(|r| r.value)
+}
+
+fn error(response: &Response) -> String {
+    let status = response.status;
+    let message = match &response.value["message"] {
+        // GitLab's messages start with the status code already, e.g. "401 Unauthorized"
+        serde_json::Value::String(s) => s
+            .strip_prefix(&format!("{} ", status.as_u16()))
+            .unwrap_or(s)
+            .to_string(),
+        serde_json::Value::Null => status.canonical_reason().unwrap_or("").to_string(),
+        other => other.to_string(),
+    };
+
+    // GitHub reports an exhausted rate limit as a 403 with no requests remaining, and
+    // secondary limits as a 403 or 429 with Retry-After
+    let exhausted = response.header("X-RateLimit-Remaining") == Some("0");
+    let reset = response
+        .header("X-RateLimit-Reset")
+        .and_then(|t| t.parse::<i64>().ok());
+    let retry_after = response.header("Retry-After");
+    match (status.as_u16(), exhausted, reset, retry_after) {
+        (401, ..) => format!("401 {message} (is the token still valid?)"),
+        (403 | 429, true, Some(reset), _) => {
+            let reset = chrono::NaiveDateTime::from_timestamp(reset, 0);
+            let reset = chrono::DateTime::<chrono::Utc>::from_utc(reset, chrono::Utc);
+            format!(
+                "rate limited until {}",
+                reset
+                    .with_timezone(&chrono::Local)
+                    .format("%l:%M%P")
+                    .to_string()
+                    .trim()
+            )
+        }
+        (403 | 429, _, _, Some(seconds)) => format!("rate limited, retry in {seconds}s"),
+        _ => format!("{} {message}", status.as_u16()),
+    }
+}
@@ -3,16 +3,22 @@ use input::{
         keyboard::{
             KeyState, {KeyboardEvent, KeyboardEventTrait},
         },
-        Event,
+        DeviceEvent, Event, EventTrait,
     },
-    Libinput, LibinputInterface,
+    DeviceCapability, Libinput, LibinputInterface,
 };
 use nix::{
     fcntl::{open, OFlag},
     sys::stat::Mode,
     unistd::close,
 };
+use serde::{Deserialize, Serialize};
 
+use crate::config::DeviceFilter;
+use crate::evdev;
+use crate::keymap::Keymap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Key {
     LCD1,
     LCD2,
@@ -24,25 +30,42 @@ pub enum Key {
     Slider(usize),
 }
 
+pub const SLIDER_POSITIONS: usize = 6;
+
 impl Key {
-    fn from_u32(x: u32) -> Option<Self> {
-        match x {
-            183 => Some(Self::LCD1),
-            184 => Some(Self::LCD2),
-            185 => Some(Self::LCD3),
-            186 => Some(Self::LCD4),
-            187 => Some(Self::Abort),
-            188 => Some(Self::Execute),
-            70 => Some(Self::Danger),
-            194 => Some(Self::Slider(0)),
-            193 => Some(Self::Slider(1)),
-            192 => Some(Self::Slider(2)),
-            191 => Some(Self::Slider(3)),
-            190 => Some(Self::Slider(4)),
-            189 => Some(Self::Slider(5)),
-            _ => None,
+    // Every logical key, in the order learn mode asks for them
+    pub fn all() -> Vec<Self> {
+        let mut keys = vec![
+            Self::LCD1,
+            Self::LCD2,
+            Self::LCD3,
+            Self::LCD4,
+            Self::Abort,
+            Self::Execute,
+            Self::Danger,
+        ];
+        keys.extend((0..SLIDER_POSITIONS).map(Self::Slider));
+        keys
+    }
+
+    // Names used in the keymap file and on the command line, e.g. lcd1 or slider3
+    pub fn name(&self) -> String {
+        match self {
+            Self::LCD1 => "lcd1".to_string(),
+            Self::LCD2 => "lcd2".to_string(),
+            Self::LCD3 => "lcd3".to_string(),
+            Self::LCD4 => "lcd4".to_string(),
+            Self::Abort => "abort".to_string(),
+            Self::Execute => "execute".to_string(),
+            Self::Danger => "danger".to_string(),
+            Self::Slider(n) => format!("slider{n}"),
         }
     }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::all().into_iter().find(|k| k.name() == name)
+    }
 }
 
 struct LibinputInterfaceRaw;
@@ -65,20 +88,473 @@ impl LibinputInterface for LibinputInterfaceRaw {
     }
 }
 
-pub fn handle_input_events<F: Fn(Key) + Send + Sync + 'static>(callback: F) {
+pub enum Input {
+    // An evdev code, and whether it was pressed or released
+    Key(u32, bool),
+    // Whether any matching keyboard is plugged in
+    Connected(bool),
+    // Sent every few milliseconds between events
+    Idle,
+}
+
+// Where key presses are read from
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Libinput,
+    // /dev/input/event* directly, for when there's no udev seat
+    Evdev,
+    // The window's own keyboard and clickable side panel, for development
+    Window,
+}
+
+pub struct Source {
+    pub backend: Backend,
+    // Take the devices exclusively (evdev only), so keys don't leak to other apps
+    pub grab: bool,
+    pub devices: Vec<DeviceFilter>,
+}
+
+// Reads input until the callback returns false. Backend errors are logged and the
+// backend restarted, rather than taking down the input thread.
+fn input_loop<F: FnMut(Input) -> bool>(source: &Source, mut callback: F) {
+    loop {
+        let result = match source.backend {
+            Backend::Libinput => run_libinput(&source.devices, &mut callback),
+            Backend::Evdev => evdev::run(&source.devices, source.grab, &mut callback),
+            // Handled by the window itself
+            Backend::Window => Ok(()),
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("input: {e}, restarting");
+                if !callback(Input::Connected(false)) {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+fn run_libinput<F: FnMut(Input) -> bool>(
+    devices: &[DeviceFilter],
+    callback: &mut F,
+) -> Result<(), String> {
     let mut libinput_context = Libinput::new_with_udev(LibinputInterfaceRaw);
-    libinput_context.udev_assign_seat("seat0").unwrap();
+    libinput_context
+        .udev_assign_seat("seat0")
+        .map_err(|_| "couldn't assign seat0")?;
+
+    // Keyboards we're listening to, by sysname. libinput adds every device already
+    // plugged in on the first dispatch, and hotplugged ones as they come and go.
+    let mut keyboards = std::collections::HashSet::new();
     loop {
-        libinput_context.dispatch().unwrap();
+        libinput_context.dispatch().map_err(|e| e.to_string())?;
         while let Some(event) = libinput_context.next() {
-            if let Event::Keyboard(KeyboardEvent::Key(evt)) = event {
-                if evt.key_state() == KeyState::Pressed {
-                    if let Some(k) = Key::from_u32(evt.key()) {
-                        callback(k)
+            let input = match event {
+                Event::Device(DeviceEvent::Added(evt)) => {
+                    let device = evt.device();
+                    if !device.has_capability(DeviceCapability::Keyboard)
+                        || !device_matches(devices, &device)
+                    {
+                        continue;
+                    }
+                    keyboards.insert(device.sysname().to_string());
+                    Input::Connected(true)
+                }
+                Event::Device(DeviceEvent::Removed(evt)) => {
+                    if !keyboards.remove(evt.device().sysname()) {
+                        continue;
                     }
+                    Input::Connected(!keyboards.is_empty())
+                }
+                Event::Keyboard(KeyboardEvent::Key(evt))
+                    if keyboards.contains(evt.device().sysname()) =>
+                {
+                    Input::Key(evt.key(), evt.key_state() == KeyState::Pressed)
                 }
+                _ => continue,
+            };
+            if !callback(input) {
+                return Ok(());
             }
         }
+        if !callback(Input::Idle) {
+            return Ok(());
+        }
         std::thread::sleep(std::time::Duration::from_millis(10));
     }
 }
+
+fn device_matches(devices: &[DeviceFilter], device: &input::Device) -> bool {
+    devices.is_empty()
+        || devices
+            .iter()
+            .any(|d| d.matches(device.name(), device.id_vendor(), device.id_product()))
+}
+
+// Calls back with the evdev code of every key press, until the callback returns false
+pub fn read_key_codes<F: FnMut(u32) -> bool>(source: &Source, mut callback: F) {
+    input_loop(source, |input| match input {
+        Input::Key(code, true) => callback(code),
+        _ => true,
+    });
+}
+
+pub fn handle_input_events<F, C>(
+    keymap: Keymap,
+    timing: Timing,
+    source: &Source,
+    callback: F,
+    connected: C,
+) where
+    F: Fn(KeyEvent) + Send + Sync + 'static,
+    C: Fn(bool),
+{
+    let mut gestures = Gestures::new(timing);
+    input_loop(source, |input| {
+        let now = std::time::Instant::now();
+        let events = match input {
+            Input::Key(code, pressed) => match keymap.key(code) {
+                Some(key) if pressed => gestures.press(key, now).into_iter().collect(),
+                Some(key) => gestures.release(key),
+                None => Vec::new(),
+            },
+            Input::Connected(c) => {
+                connected(c);
+                Vec::new()
+            }
+            Input::Idle => gestures.tick(now),
+        };
+        for event in events {
+            callback(event);
+        }
+        true
+    });
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KeyEvent {
+    Press(Key),
+    Release(Key),
+    // Fired while the key is still down, once it's been held past the threshold
+    LongPress(Key),
+    // Sent instead of Press for the second of two quick presses
+    DoubleTap(Key),
+    // Sent instead of both keys' presses when a second key goes down within the
+    // chord window of the first
+    Chord(Key, Key),
+}
+
+impl KeyEvent {
+    // The key press this amounts to, for pages with no binding for the event itself
+    pub fn as_press(&self) -> Option<Key> {
+        match self {
+            Self::Press(k) | Self::DoubleTap(k) | Self::Chord(_, k) => Some(*k),
+            Self::Release(_) | Self::LongPress(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Timing {
+    pub long_press: std::time::Duration,
+    pub double_tap: std::time::Duration,
+    pub chord: std::time::Duration,
+}
+
+// Keys that start a chord binding. Their presses wait out the chord window, so
+// the first key of a chord never also fires on its own.
+const CHORD_KEYS: [Key; 4] = [Key::LCD1, Key::LCD4, Key::Abort, Key::Execute];
+
+struct Held {
+    key: Key,
+    since: std::time::Instant,
+    // Held back until the chord window passes or the key is released
+    pending: bool,
+    // Set once a long press has fired, or if the key was part of a chord
+    done: bool,
+}
+
+// Turns raw presses and releases into key events. The caller passes the time in,
+// so it can be driven without libinput.
+pub struct Gestures {
+    timing: Timing,
+    held: Vec<Held>,
+    last_press: Option<(Key, std::time::Instant)>,
+}
+
+impl Gestures {
+    pub fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            held: Vec::new(),
+            last_press: None,
+        }
+    }
+
+    pub fn press(&mut self, key: Key, now: std::time::Instant) -> Option<KeyEvent> {
+        // The slider positions make up one control, so they're never held or chorded
+        if let Key::Slider(_) = key {
+            return Some(KeyEvent::Press(key));
+        }
+        if self.held.iter().any(|h| h.key == key) {
+            return None;
+        }
+
+        let mut held = Held {
+            key,
+            since: now,
+            pending: false,
+            done: false,
+        };
+        let event = if let Some(other) = self.held.iter_mut().find(|h| h.pending) {
+            other.pending = false;
+            other.done = true;
+            held.done = true;
+            Some(KeyEvent::Chord(other.key, key))
+        } else if matches!(self.last_press, Some((k, t)) if k == key && now - t <= self.timing.double_tap)
+        {
+            self.last_press = None;
+            Some(KeyEvent::DoubleTap(key))
+        } else {
+            self.last_press = Some((key, now));
+            held.pending = CHORD_KEYS.contains(&key);
+            (!held.pending).then_some(KeyEvent::Press(key))
+        };
+
+        self.held.push(held);
+        event
+    }
+
+    pub fn release(&mut self, key: Key) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        if let Some(i) = self.held.iter().position(|h| h.key == key) {
+            // Tapped and released inside the chord window
+            if self.held.remove(i).pending {
+                events.push(KeyEvent::Press(key));
+            }
+        }
+        events.push(KeyEvent::Release(key));
+        events
+    }
+
+    pub fn tick(&mut self, now: std::time::Instant) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        let mut long_press = false;
+        for held in &mut self.held {
+            if held.pending && now - held.since >= self.timing.chord {
+                held.pending = false;
+                events.push(KeyEvent::Press(held.key));
+            }
+            if !held.pending && !held.done && now - held.since >= self.timing.long_press {
+                held.done = true;
+                long_press = true;
+                events.push(KeyEvent::LongPress(held.key));
+            }
+        }
+
+        // A long press isn't the first half of a double tap
+        if long_press {
+            self.last_press = None;
+        }
+        events
+    }
+
+    // Whether a press is being held back, so the caller knows to keep ticking
+    pub fn waiting(&self) -> bool {
+        self.held.iter().any(|h| h.pending)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Travel {
+    Up,
+    Down,
+    Still,
+}
+
+// The slider keys as one control: where it is, which way it last moved and how
+// long it has rested there. Position 0 is the top of its travel.
+pub struct Slider {
+    position: Option<usize>,
+    travel: Travel,
+    since: std::time::Instant,
+}
+
+impl Slider {
+    pub fn new() -> Self {
+        Self {
+            position: None,
+            travel: Travel::Still,
+            since: std::time::Instant::now(),
+        }
+    }
+
+    // Slider(0) is the bottom of the travel
+    pub fn moved(&mut self, key_position: usize, now: std::time::Instant) {
+        let position = SLIDER_POSITIONS - 1 - key_position.min(SLIDER_POSITIONS - 1);
+        self.travel = match self.position {
+            Some(p) if position < p => Travel::Up,
+            Some(p) if position > p => Travel::Down,
+            _ => Travel::Still,
+        };
+        self.position = Some(position);
+        self.since = now;
+    }
+
+    // None until the slider has been touched
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    pub fn travel(&self) -> Travel {
+        self.travel
+    }
+
+    pub fn dwell(&self, now: std::time::Instant) -> std::time::Duration {
+        now.saturating_duration_since(self.since)
+    }
+
+    // Which end the slider is resting at, if either
+    pub fn end(&self) -> Option<Travel> {
+        match self.position? {
+            0 => Some(Travel::Up),
+            p if p == SLIDER_POSITIONS - 1 => Some(Travel::Down),
+            _ => None,
+        }
+    }
+
+    // How many rows to scroll per heartbeat while resting at an end: one, plus
+    // one for every second it has rested there
+    pub fn speed(&self, now: std::time::Instant) -> usize {
+        1 + (self.dwell(now).as_secs() as usize).min(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn gestures() -> Gestures {
+        Gestures::new(Timing {
+            long_press: Duration::from_millis(600),
+            double_tap: Duration::from_millis(300),
+            chord: Duration::from_millis(80),
+        })
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn press() {
+        let mut g = gestures();
+        let t = Instant::now();
+        assert_eq!(g.press(Key::LCD2, t), Some(KeyEvent::Press(Key::LCD2)));
+        assert_eq!(g.release(Key::LCD2), vec![KeyEvent::Release(Key::LCD2)]);
+        assert_eq!(
+            g.press(Key::Slider(3), t),
+            Some(KeyEvent::Press(Key::Slider(3)))
+        );
+    }
+
+    #[test]
+    fn chord_keys_wait_for_the_window() {
+        let mut g = gestures();
+        let t = Instant::now();
+        assert_eq!(g.press(Key::LCD1, t), None);
+        assert!(g.waiting());
+        assert_eq!(g.tick(ms(t, 50)), vec![]);
+        assert_eq!(g.tick(ms(t, 80)), vec![KeyEvent::Press(Key::LCD1)]);
+        assert!(!g.waiting());
+
+        // Released before the window passes
+        assert_eq!(g.release(Key::LCD1), vec![KeyEvent::Release(Key::LCD1)]);
+        let t = ms(t, 1000);
+        assert_eq!(g.press(Key::Execute, t), None);
+        assert_eq!(
+            g.release(Key::Execute),
+            vec![
+                KeyEvent::Press(Key::Execute),
+                KeyEvent::Release(Key::Execute)
+            ]
+        );
+    }
+
+    #[test]
+    fn double_tap() {
+        let mut g = gestures();
+        let t = Instant::now();
+        g.press(Key::LCD2, t);
+        g.release(Key::LCD2);
+        assert_eq!(
+            g.press(Key::LCD2, ms(t, 200)),
+            Some(KeyEvent::DoubleTap(Key::LCD2))
+        );
+        g.release(Key::LCD2);
+        // A third tap starts over
+        assert_eq!(
+            g.press(Key::LCD2, ms(t, 400)),
+            Some(KeyEvent::Press(Key::LCD2))
+        );
+        g.release(Key::LCD2);
+        assert_eq!(
+            g.press(Key::LCD2, ms(t, 800)),
+            Some(KeyEvent::Press(Key::LCD2))
+        );
+
+        // The second tap of a chord key isn't held back
+        let t = ms(t, 2000);
+        g.press(Key::Execute, t);
+        g.release(Key::Execute);
+        assert_eq!(
+            g.press(Key::Execute, ms(t, 150)),
+            Some(KeyEvent::DoubleTap(Key::Execute))
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let mut g = gestures();
+        let t = Instant::now();
+        g.press(Key::Abort, t);
+        assert_eq!(g.tick(ms(t, 100)), vec![KeyEvent::Press(Key::Abort)]);
+        assert_eq!(g.tick(ms(t, 599)), vec![]);
+        assert_eq!(g.tick(ms(t, 600)), vec![KeyEvent::LongPress(Key::Abort)]);
+        assert_eq!(g.tick(ms(t, 900)), vec![]);
+        g.release(Key::Abort);
+
+        // A long press isn't the first half of a double tap
+        assert_eq!(g.press(Key::Abort, ms(t, 700)), None);
+    }
+
+    #[test]
+    fn chord() {
+        let mut g = gestures();
+        let t = Instant::now();
+        assert_eq!(g.press(Key::LCD1, t), None);
+        assert_eq!(
+            g.press(Key::LCD4, ms(t, 40)),
+            Some(KeyEvent::Chord(Key::LCD1, Key::LCD4))
+        );
+        // Neither key fires on its own afterwards, not even as a long press
+        assert_eq!(g.tick(ms(t, 1000)), vec![]);
+        assert_eq!(g.release(Key::LCD1), vec![KeyEvent::Release(Key::LCD1)]);
+        assert_eq!(g.release(Key::LCD4), vec![KeyEvent::Release(Key::LCD4)]);
+    }
+
+    #[test]
+    fn no_chord_after_the_window() {
+        let mut g = gestures();
+        let t = Instant::now();
+        g.press(Key::Execute, t);
+        assert_eq!(g.tick(ms(t, 100)), vec![KeyEvent::Press(Key::Execute)]);
+        assert_eq!(g.press(Key::Abort, ms(t, 200)), None);
+        assert_eq!(g.tick(ms(t, 280)), vec![KeyEvent::Press(Key::Abort)]);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::config;
+use crate::keyboard::{self, Key};
+
+// Maps evdev key codes to logical keys. The file is a JSON object from evdev codes
+// or key names to logical key names, e.g. {"KEY_F13": "lcd1", "70": "danger"}.
+pub struct Keymap {
+    codes: HashMap<u32, Key>,
+}
+
+// The board the console was built around: F13-F18 for the LCD and action keys,
+// scroll lock for Danger and F19-F24 for the slider.
+const DEFAULT: &[(u32, Key)] = &[
+    (183, Key::LCD1),
+    (184, Key::LCD2),
+    (185, Key::LCD3),
+    (186, Key::LCD4),
+    (187, Key::Abort),
+    (188, Key::Execute),
+    (70, Key::Danger),
+    (194, Key::Slider(0)),
+    (193, Key::Slider(1)),
+    (192, Key::Slider(2)),
+    (191, Key::Slider(3)),
+    (190, Key::Slider(4)),
+    (189, Key::Slider(5)),
+];
+
+// Names from linux/input-event-codes.h for the keys macro pads tend to send
+const NAMES: &[(&str, u32)] = &[
+    ("KEY_ESC", 1),
+    ("KEY_1", 2),
+    ("KEY_2", 3),
+    ("KEY_3", 4),
+    ("KEY_4", 5),
+    ("KEY_5", 6),
+    ("KEY_6", 7),
+    ("KEY_7", 8),
+    ("KEY_8", 9),
+    ("KEY_9", 10),
+    ("KEY_0", 11),
+    ("KEY_MINUS", 12),
+    ("KEY_EQUAL", 13),
+    ("KEY_BACKSPACE", 14),
+    ("KEY_TAB", 15),
+    ("KEY_Q", 16),
+    ("KEY_W", 17),
+    ("KEY_E", 18),
+    ("KEY_R", 19),
+    ("KEY_T", 20),
+    ("KEY_Y", 21),
+    ("KEY_U", 22),
+    ("KEY_I", 23),
+    ("KEY_O", 24),
+    ("KEY_P", 25),
+    ("KEY_ENTER", 28),
+    ("KEY_LEFTCTRL", 29),
+    ("KEY_A", 30),
+    ("KEY_S", 31),
+    ("KEY_D", 32),
+    ("KEY_F", 33),
+    ("KEY_G", 34),
+    ("KEY_H", 35),
+    ("KEY_J", 36),
+    ("KEY_K", 37),
+    ("KEY_L", 38),
+    ("KEY_LEFTSHIFT", 42),
+    ("KEY_Z", 44),
+    ("KEY_X", 45),
+    ("KEY_C", 46),
+    ("KEY_V", 47),
+    ("KEY_B", 48),
+    ("KEY_N", 49),
+    ("KEY_M", 50),
+    ("KEY_RIGHTSHIFT", 54),
+    ("KEY_LEFTALT", 56),
+    ("KEY_SPACE", 57),
+    ("KEY_CAPSLOCK", 58),
+    ("KEY_F1", 59),
+    ("KEY_F2", 60),
+    ("KEY_F3", 61),
+    ("KEY_F4", 62),
+    ("KEY_F5", 63),
+    ("KEY_F6", 64),
+    ("KEY_F7", 65),
+    ("KEY_F8", 66),
+    ("KEY_F9", 67),
+    ("KEY_F10", 68),
+    ("KEY_NUMLOCK", 69),
+    ("KEY_SCROLLLOCK", 70),
+    ("KEY_KP7", 71),
+    ("KEY_KP8", 72),
+    ("KEY_KP9", 73),
+    ("KEY_KPMINUS", 74),
+    ("KEY_KP4", 75),
+    ("KEY_KP5", 76),
+    ("KEY_KP6", 77),
+    ("KEY_KPPLUS", 78),
+    ("KEY_KP1", 79),
+    ("KEY_KP2", 80),
+    ("KEY_KP3", 81),
+    ("KEY_KP0", 82),
+    ("KEY_KPDOT", 83),
+    ("KEY_F11", 87),
+    ("KEY_F12", 88),
+    ("KEY_KPENTER", 96),
+    ("KEY_RIGHTCTRL", 97),
+    ("KEY_KPSLASH", 98),
+    ("KEY_SYSRQ", 99),
+    ("KEY_RIGHTALT", 100),
+    ("KEY_HOME", 102),
+    ("KEY_UP", 103),
+    ("KEY_PAGEUP", 104),
+    ("KEY_LEFT", 105),
+    ("KEY_RIGHT", 106),
+    ("KEY_END", 107),
+    ("KEY_DOWN", 108),
+    ("KEY_PAGEDOWN", 109),
+    ("KEY_INSERT", 110),
+    ("KEY_DELETE", 111),
+    ("KEY_MUTE", 113),
+    ("KEY_VOLUMEDOWN", 114),
+    ("KEY_VOLUMEUP", 115),
+    ("KEY_PAUSE", 119),
+    ("KEY_KPASTERISK", 55),
+    ("KEY_LEFTMETA", 125),
+    ("KEY_RIGHTMETA", 126),
+    ("KEY_NEXTSONG", 163),
+    ("KEY_PLAYPAUSE", 164),
+    ("KEY_PREVIOUSSONG", 165),
+    ("KEY_F13", 183),
+    ("KEY_F14", 184),
+    ("KEY_F15", 185),
+    ("KEY_F16", 186),
+    ("KEY_F17", 187),
+    ("KEY_F18", 188),
+    ("KEY_F19", 189),
+    ("KEY_F20", 190),
+    ("KEY_F21", 191),
+    ("KEY_F22", 192),
+    ("KEY_F23", 193),
+    ("KEY_F24", 194),
+];
+
+fn code_from_name(name: &str) -> Option<u32> {
+    if let Ok(code) = name.parse() {
+        return Some(code);
+    }
+    let name = name.to_uppercase();
+    let name = if name.starts_with("KEY_") {
+        name
+    } else {
+        format!("KEY_{name}")
+    };
+    NAMES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+}
+
+fn name_from_code(code: u32) -> String {
+    match NAMES.iter().find(|(_, c)| *c == code) {
+        Some((name, _)) => name.to_string(),
+        None => code.to_string(),
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            codes: DEFAULT.iter().copied().collect(),
+        }
+    }
+}
+
+impl Keymap {
+    pub fn path() -> std::path::PathBuf {
+        config::home_file(".console_gui_keymap.json")
+    }
+
+    // Ok(None) means there's no keymap file, so the default board is assumed
+    pub fn check() -> Result<Option<Self>, String> {
+        Self::read(&Self::path())
+    }
+
+    fn read(path: &std::path::Path) -> Result<Option<Self>, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        Self::parse(&contents).map(Some)
+    }
+
+    pub fn load() -> Self {
+        match Self::check() {
+            Ok(keymap) => keymap.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("invalid keymap {}: {}", Self::path().display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let entries: HashMap<String, String> =
+            serde_json::from_str(contents).map_err(|e| e.to_string())?;
+
+        let mut codes = HashMap::new();
+        for (code, key) in entries {
+            let code = code_from_name(&code).ok_or(format!("unknown key code {code}"))?;
+            let key = Key::from_name(&key).ok_or(format!("unknown logical key {key}"))?;
+            codes.insert(code, key);
+        }
+        Ok(Self { codes })
+    }
+
+    pub fn key(&self, code: u32) -> Option<Key> {
+        self.codes.get(&code).copied()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let entries: serde_json::Map<String, serde_json::Value> = self
+            .codes
+            .iter()
+            .map(|(code, key)| (name_from_code(*code), key.name().into()))
+            .collect();
+        let contents = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+        std::fs::write(Self::path(), contents).map_err(|e| e.to_string())
+    }
+}
+
+// Pressed instead of a key the board doesn't have. Learning listens to every
+// keyboard, so it can come from the desktop's own.
+const SKIP: u32 = 1; // KEY_ESC
+
+// Walk through each logical key, recording whichever key is pressed for it
+pub fn learn() -> Result<(), String> {
+    // Listen to every keyboard, since the filters may be what's being set up, and
+    // don't grab any of them
+    let source = keyboard::Source {
+        backend: config::Config::load().input_backend,
+        grab: false,
+        devices: Vec::new(),
+    };
+    if source.backend == keyboard::Backend::Window {
+        return Err("learn-keys needs the libinput or evdev backend".to_string());
+    }
+    let mut keymap = Keymap {
+        codes: HashMap::new(),
+    };
+
+    for key in Key::all() {
+        println!("press the key for {} (Esc to skip it)", key.name());
+        keyboard::read_key_codes(&source, |code| {
+            if code == SKIP {
+                println!("  skipped {}", key.name());
+                return false;
+            }
+            if let Some(existing) = keymap.key(code) {
+                println!(
+                    "{} is already {}, press another key",
+                    name_from_code(code),
+                    existing.name()
+                );
+                return true;
+            }
+
+            println!("  {} -> {}", name_from_code(code), key.name());
+            keymap.codes.insert(code, key);
+            false
+        });
+    }
+
+    keymap.save()?;
+    println!("saved {}", Keymap::path().display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_names_and_codes() {
+        assert_eq!(code_from_name("KEY_F13"), Some(183));
+        // The prefix and case are optional, and plain numbers are codes
+        assert_eq!(code_from_name("f13"), Some(183));
+        assert_eq!(code_from_name("scrolllock"), Some(70));
+        assert_eq!(code_from_name("70"), Some(70));
+        assert_eq!(code_from_name("KEY_NOPE"), None);
+
+        assert_eq!(name_from_code(183), "KEY_F13");
+        // Codes without a name are saved as numbers
+        assert_eq!(name_from_code(250), "250");
+        for (name, code) in NAMES {
+            assert_eq!(
+                code_from_name(&name_from_code(*code)),
+                Some(*code),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_keymaps() {
+        let keymap =
+            Keymap::parse(r#"{"KEY_F13": "lcd1", "f14": "abort", "70": "danger"}"#).unwrap();
+        assert_eq!(keymap.key(183), Some(Key::LCD1));
+        assert_eq!(keymap.key(184), Some(Key::Abort));
+        assert_eq!(keymap.key(70), Some(Key::Danger));
+        // Only what's in the file is mapped, not the default board
+        assert_eq!(keymap.key(185), None);
+
+        assert_eq!(
+            Keymap::parse(r#"{"KEY_NOPE": "lcd1"}"#).err().unwrap(),
+            "unknown key code KEY_NOPE"
+        );
+        assert_eq!(
+            Keymap::parse(r#"{"KEY_F13": "lcd9"}"#).err().unwrap(),
+            "unknown logical key lcd9"
+        );
+        assert!(Keymap::parse("[]").is_err());
+    }
+
+    #[test]
+    fn missing_file_means_the_default_board() {
+        let dir = std::env::temp_dir().join(format!("console-gui-keymap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keymap.json");
+
+        assert!(Keymap::read(&path).unwrap().is_none());
+
+        std::fs::write(&path, r#"{"KEY_A": "execute"}"#).unwrap();
+        let keymap = Keymap::read(&path).unwrap().unwrap();
+        assert_eq!(keymap.key(30), Some(Key::Execute));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Keymap::read(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use eframe::egui;
+use eframe::egui::{FontDefinitions, FontFamily, TextStyle};
+
+// The panel the original sizes were tuned on
+pub const DESIGN_SIZE: egui::Vec2 = egui::Vec2::new(800.0, 480.0);
+
+// Sizes for the console's screen. Everything scales with the window relative to the
+// design size, or by the config's ui_scale when it's set, and text-driven sizes come
+// from the fonts' measured rows.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub scale: f32,
+    size: egui::Vec2,
+    mono_row: f32,
+    mono_width: f32,
+    heading_row: f32,
+}
+
+impl Layout {
+    pub fn new(size: egui::Vec2, ui_scale: Option<f32>) -> Self {
+        // In 5% steps, so resizing the window doesn't rebuild the fonts every frame
+        let fit = (size.x / DESIGN_SIZE.x).min(size.y / DESIGN_SIZE.y);
+        let fit = (fit * 20.0).round() / 20.0;
+        let scale = ui_scale.unwrap_or(fit).max(0.25);
+        // Estimates for 24pt fonts, until they can be measured
+        Self {
+            scale,
+            size,
+            mono_row: 24.0 * scale,
+            mono_width: 12.4 * scale,
+            heading_row: 24.0 * scale,
+        }
+    }
+
+    pub fn measure(self, fonts: &egui::epaint::text::Fonts) -> Self {
+        Self {
+            mono_row: fonts.row_height(TextStyle::Monospace),
+            mono_width: fonts.glyph_width(TextStyle::Monospace, 'W'),
+            heading_row: fonts.row_height(TextStyle::Heading),
+            ..self
+        }
+    }
+
+    // A size on the design panel, scaled to this one
+    pub fn px(&self, points: f32) -> f32 {
+        points * self.scale
+    }
+
+    pub fn fonts(&self) -> FontDefinitions {
+        let mut font_def = FontDefinitions::default();
+        font_def
+            .family_and_size
+            .insert(TextStyle::Heading, (FontFamily::Proportional, 24.));
+        font_def
+            .family_and_size
+            .insert(TextStyle::Monospace, (FontFamily::Monospace, 24.));
+        for (_, size) in font_def.family_and_size.values_mut() {
+            *size *= self.scale;
+        }
+        font_def
+    }
+
+    // Padding around text in a frame, and between items in a row
+    pub fn margin(&self) -> f32 {
+        self.px(5.0)
+    }
+
+    pub fn gap(&self) -> f32 {
+        self.px(10.0)
+    }
+
+    // Around each page's content
+    pub fn page_margin(&self) -> f32 {
+        self.px(20.0)
+    }
+
+    // The command panel fits a single character across, so labels stack one
+    // character per line beside the LCD keys
+    pub fn panel_width(&self) -> f32 {
+        self.mono_width * 1.6 + 2.0 * self.margin()
+    }
+
+    pub fn command_height(&self, name: &str) -> f32 {
+        name.len() as f32 * self.mono_row + 2.0 * self.gap()
+    }
+
+    // The clock's frame, plus spacing above it
+    pub fn footer_height(&self) -> f32 {
+        self.mono_row + 2.0 * self.margin() + self.px(3.0)
+    }
+
+    // Lines a shortcut up after its group's " 1 " label and its stroke
+    pub fn indent(&self) -> f32 {
+        3.0 * self.mono_width + self.px(2.0)
+    }
+
+    // A list entry: a framed time and title over a detail line
+    fn entry_height(&self) -> f32 {
+        self.mono_row + 2.0 * self.margin() + self.heading_row + self.gap() + self.px(6.0)
+    }
+
+    // How many list entries fit, leaving room for a page header (the calendar, or an
+    // armed action)
+    pub fn list_rows(&self) -> usize {
+        let height = self.size.y
+            - self.footer_height()
+            - 2.0 * self.page_margin()
+            - 1.5 * self.entry_height();
+        (height / self.entry_height()).floor().max(1.0) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::{self, Slider};
+    use crate::ListCursor;
+
+    #[test]
+    fn tall_screens_show_more_rows_than_the_slider_has_positions() {
+        let layout = Layout::new(egui::Vec2::new(800.0, 1600.0), Some(1.0));
+        let rows = layout.list_rows();
+        assert!(rows > keyboard::SLIDER_POSITIONS, "{rows}");
+
+        // The slider's ends still reach the first and last rows
+        let now = std::time::Instant::now();
+        let mut slider = Slider::new();
+        let mut cursor = ListCursor::default();
+        slider.moved(keyboard::SLIDER_POSITIONS - 1, now);
+        cursor.moved(&slider, 100, rows);
+        assert_eq!(cursor.index(), 0);
+        slider.moved(0, now);
+        cursor.moved(&slider, 100, rows);
+        assert_eq!(cursor.index(), rows - 1);
+    }
+}
@@ -1,23 +1,46 @@
-use eframe::egui::{FontDefinitions, FontFamily};
 use eframe::{egui, epi};
 
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
+mod actions;
+mod cache;
 mod calendar;
+mod cli;
+mod clock;
 mod command;
+mod config;
+mod control;
+mod credentials;
+mod desktop;
+mod evdev;
 mod github;
+mod gitlab;
+mod http;
 mod keyboard;
+mod keymap;
+mod layout;
+#[cfg(any(test, feature = "dev"))]
+mod mock_github;
+mod recording;
+mod scheduler;
+mod snapshot;
 mod style;
+mod webhook;
 
 mod home;
 mod pull_requests;
 mod shortcuts;
 
+// How often the UI is refreshed and time-based page state updated
+const HEARTBEAT: std::time::Duration = std::time::Duration::from_millis(200);
+
 #[derive(Clone)]
 pub struct App {
     data: Arc<Mutex<AppState>>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CalendarEvent {
     title: String,
     time: String,
@@ -25,21 +48,123 @@ pub struct CalendarEvent {
     zoom_url: Option<String>,
 }
 
-#[derive(Debug)]
-pub struct GitHubNotification {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
     title: String,
     action: String,
     repository: String,
+    account: String,
     time: i64,
     url: String,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PullRequest {
+    // What actions address the PR by, besides its number: the GraphQL node ID on
+    // GitHub, and on GitLab the project's ID, since MR numbers are per project
+    provider_id: String,
     title: String,
     url: String,
     updated_at: i64,
     repo_name: String,
+    owner: String,
+    number: u64,
+    account: String,
+    state: PrState,
+    closed_at: Option<i64>,
+    checks: Option<CheckState>,
+    review: Option<ReviewDecision>,
+    comments: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PrState {
+    Open,
+    Draft,
+    Merged,
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CheckState {
+    Pending,
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReviewDecision {
+    Approved,
+    ChangesRequested,
+    ReviewRequired,
+}
+
+// A notification sent over the session bus by another application
+#[derive(Debug, Serialize)]
+pub struct DesktopNotification {
+    id: u32,
+    app: String,
+    summary: String,
+    body: String,
+    // (key, label) pairs
+    actions: Vec<(String, String)>,
+    time: i64,
+}
+
+pub struct Toast {
+    text: String,
+    expires: std::time::Instant,
+}
+
+// Selection in a list driven by the slider: each position picks the visible row
+// beside it, and resting at either end scrolls, faster the longer it rests.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ListCursor {
+    // Relative to the first visible row
+    selected: usize,
+    scroll: usize,
+}
+
+impl ListCursor {
+    pub fn index(&self) -> usize {
+        self.selected + self.scroll
+    }
+
+    // rows is how many entries are on screen, which can be more or fewer than the
+    // slider has positions, so the positions are spread over them
+    pub fn moved(&mut self, slider: &keyboard::Slider, len: usize, rows: usize) {
+        if let Some(position) = slider.position() {
+            let last = keyboard::SLIDER_POSITIONS - 1;
+            self.selected = (position * rows.saturating_sub(1) + last / 2) / last;
+        }
+        self.clamp(len, rows);
+    }
+
+    // Called on the heartbeat, to scroll while the slider rests at an end
+    pub fn tick(
+        &mut self,
+        slider: &keyboard::Slider,
+        len: usize,
+        rows: usize,
+        now: std::time::Instant,
+    ) {
+        for _ in 0..slider.speed(now) {
+            match slider.end() {
+                Some(keyboard::Travel::Up) => self.scroll = self.scroll.saturating_sub(1),
+                Some(keyboard::Travel::Down) if self.index() + 1 < len => self.scroll += 1,
+                _ => break,
+            }
+        }
+        self.clamp(len, rows);
+    }
+
+    fn clamp(&mut self, len: usize, rows: usize) {
+        self.scroll = self.scroll.min(len.saturating_sub(1));
+        self.selected = self
+            .selected
+            .min(len.saturating_sub(self.scroll + 1))
+            .min(rows.saturating_sub(1));
+    }
 }
 
 pub struct Command {
@@ -70,6 +195,7 @@ impl Command {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 enum PageState {
     Home(home::HomeState),
     Shortcuts { selected: Option<usize> },
@@ -91,48 +217,306 @@ impl PageState {
 }
 
 pub struct AppState {
+    config: config::Config,
     page: PageState,
     frame: Option<epi::Frame>,
-    clock: String,
+    clock: clock::SharedClock,
     calendar: Option<CalendarEvent>,
-    notifications: Vec<GitHubNotification>,
+    notifications: Vec<Notification>,
+    desktop_notifications: Vec<DesktopNotification>,
     open_prs: Vec<PullRequest>,
+    draft_prs: Vec<PullRequest>,
     closed_prs: Vec<PullRequest>,
+    review_prs: Vec<PullRequest>,
+    actions: Option<tokio::sync::mpsc::UnboundedSender<actions::ActionRequest>>,
+    desktop: Option<tokio::sync::mpsc::UnboundedSender<desktop::Request>>,
+    sources: std::collections::BTreeMap<String, scheduler::SourceHealth>,
+    // None refreshes every source, Some(account) just that account's
+    refresh: tokio::sync::watch::Sender<Option<String>>,
+    toast: Option<Toast>,
+    slider: keyboard::Slider,
+    keyboard_connected: bool,
+    // Set when the window's keyboard stands in for the console's
+    window_keys: Option<keyboard::Gestures>,
+    recorder: Option<recording::Recorder>,
+    layout: layout::Layout,
+    cached_at: Option<i64>,
+    cache_dirty: bool,
 }
 
 impl AppState {
     fn new() -> Self {
+        Self::with_config(config::Config::load())
+    }
+
+    fn with_config(config: config::Config) -> Self {
         Self {
+            layout: layout::Layout::new(layout::DESIGN_SIZE, config.ui_scale),
+            config,
             page: PageState::home(),
             frame: None,
-            clock: Self::clock_time(),
+            clock: clock::system(),
             calendar: None,
             notifications: Vec::new(),
+            desktop_notifications: Vec::new(),
             open_prs: Vec::new(),
+            draft_prs: Vec::new(),
             closed_prs: Vec::new(),
+            review_prs: Vec::new(),
+            actions: None,
+            desktop: None,
+            sources: std::collections::BTreeMap::new(),
+            refresh: tokio::sync::watch::channel(None).0,
+            toast: None,
+            slider: keyboard::Slider::new(),
+            keyboard_connected: false,
+            window_keys: None,
+            recorder: None,
+            cached_at: None,
+            cache_dirty: false,
         }
     }
 
-    fn clock_time() -> String {
-        chrono::prelude::Local::now()
-            .format(" %h %d  %l:%M%P ")
-            .to_string()
+    // The page's bindings get the first look at an event, then the global ones.
+    // Anything left over that amounts to a key press goes to the page as one.
+    fn handle_key_event(&mut self, event: keyboard::KeyEvent, now: std::time::Instant) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event);
+        }
+        if let keyboard::KeyEvent::Press(keyboard::Key::Slider(n)) = event {
+            self.slider.moved(n, now);
+        }
+
+        let handled = match self.page {
+            PageState::Home(_) => self.bind_home(event),
+            PageState::Shortcuts { .. } | PageState::PullRequests(_) => false,
+        } || self.bind_global(event);
+
+        match event.as_press() {
+            Some(key) if !handled => match self.page {
+                PageState::Home(_) => self.handle_kbd_home(key),
+                PageState::Shortcuts { .. } => self.handle_kbd_shortcuts(key),
+                PageState::PullRequests(_) => self.handle_kbd_pull_requests(key),
+            },
+            _ => (),
+        }
+        self.cache_dirty = true;
+
+        if let Some(frame) = self.frame.as_ref() {
+            frame.request_repaint();
+        }
+    }
+
+    // Time-based updates to the current page, like scrolling while the slider rests
+    // at an end
+    fn heartbeat(&mut self, now: std::time::Instant) {
+        match self.page {
+            PageState::Home(_) => self.heartbeat_home(now),
+            PageState::Shortcuts { .. } => (),
+            PageState::PullRequests { .. } => self.heartbeat_pulls(now),
+        }
+    }
+
+    // Development input: 1-4 for the LCD keys, Esc for Abort, Enter for Execute,
+    // D for Danger and the up/down arrows to move the slider
+    fn window_input(&mut self, ctx: &egui::CtxRef) {
+        use keyboard::Key;
+        let now = std::time::Instant::now();
+        let mut events = Vec::new();
+
+        let gestures = match &mut self.window_keys {
+            Some(g) => g,
+            None => return,
+        };
+        for event in &ctx.input().events {
+            let (key, pressed) = match event {
+                egui::Event::Key { key, pressed, .. } => (*key, *pressed),
+                _ => continue,
+            };
+
+            let key = match key {
+                egui::Key::Num1 => Key::LCD1,
+                egui::Key::Num2 => Key::LCD2,
+                egui::Key::Num3 => Key::LCD3,
+                egui::Key::Num4 => Key::LCD4,
+                egui::Key::Escape => Key::Abort,
+                egui::Key::Enter => Key::Execute,
+                egui::Key::D => Key::Danger,
+                egui::Key::ArrowUp | egui::Key::ArrowDown if pressed => {
+                    let last = keyboard::SLIDER_POSITIONS - 1;
+                    let position = match (key, self.slider.position()) {
+                        (egui::Key::ArrowUp, p) => p.map_or(0, |p| p.saturating_sub(1)),
+                        (_, p) => p.map_or(last, |p| (p + 1).min(last)),
+                    };
+                    Key::Slider(last - position)
+                }
+                _ => continue,
+            };
+
+            if pressed {
+                events.extend(gestures.press(key, now));
+            } else {
+                events.extend(gestures.release(key));
+            }
+        }
+        events.extend(gestures.tick(now));
+        // Nothing else wakes the window when a held-back press is due
+        if gestures.waiting() {
+            ctx.request_repaint();
+        }
+
+        for event in events {
+            self.handle_key_event(event, now);
+        }
+    }
+
+    // A click on one of the side panel's LCD labels
+    fn window_tap(&mut self, key: keyboard::Key) {
+        let now = std::time::Instant::now();
+        if let Some(gestures) = &mut self.window_keys {
+            let events: Vec<_> = gestures
+                .press(key, now)
+                .into_iter()
+                .chain(gestures.release(key))
+                .collect();
+            for event in events {
+                self.handle_key_event(event, now);
+            }
+        }
+    }
+
+    // A chord's keys need to be in keyboard::CHORD_KEYS
+    fn bind_global(&mut self, event: keyboard::KeyEvent) -> bool {
+        use keyboard::{Key, KeyEvent};
+        match event {
+            KeyEvent::Chord(Key::Execute, Key::Abort)
+            | KeyEvent::Chord(Key::Abort, Key::Execute) => {
+                self.refresh_all();
+                self.show_toast("refreshing".to_string());
+            }
+            KeyEvent::Chord(Key::LCD1, Key::LCD4)
+            | KeyEvent::Chord(Key::LCD4, Key::LCD1)
+            | KeyEvent::LongPress(Key::Abort) => self.page = PageState::home(),
+            _ => return false,
+        }
+        true
+    }
+
+    // Ask every data source to fetch immediately
+    fn refresh_all(&self) {
+        let _ = self.refresh.send(None);
+    }
+
+    fn refresh_account(&self, account: &str) {
+        let _ = self.refresh.send(Some(account.to_string()));
+    }
+
+    fn show_toast(&mut self, text: String) {
+        self.toast = Some(Toast {
+            text,
+            expires: std::time::Instant::now() + std::time::Duration::from_secs(10),
+        });
+    }
+
+    fn clock_text(&self) -> String {
+        self.clock.now().format(" %h %d  %l:%M%P ").to_string()
+    }
+
+    // Replace one account's notifications, keeping the list sorted by time
+    fn set_notifications(&mut self, account: &str, notifications: Vec<Notification>) {
+        self.notifications.retain(|n| n.account != account);
+        self.notifications.extend(notifications);
+        self.notifications
+            .sort_by_key(|n| std::cmp::Reverse(n.time));
+    }
+
+    fn set_pulls(
+        &mut self,
+        account: &str,
+        open: Vec<PullRequest>,
+        drafts: Vec<PullRequest>,
+        closed: Vec<PullRequest>,
+        review: Vec<PullRequest>,
+    ) {
+        for (list, prs) in [
+            (&mut self.open_prs, open),
+            (&mut self.draft_prs, drafts),
+            (&mut self.closed_prs, closed),
+            (&mut self.review_prs, review),
+        ] {
+            list.retain(|pr| pr.account != account);
+            list.extend(prs);
+            list.sort_by_key(|pr| std::cmp::Reverse(pr.closed_at.unwrap_or(pr.updated_at)));
+        }
+    }
+
+    // Only tag items with their account when more than one is configured
+    fn account_tag(&self, account: &str) -> String {
+        if self.config.github_accounts.len() + self.config.gitlab_accounts.len() > 1 {
+            format!("[{account}] ")
+        } else {
+            String::new()
+        }
+    }
+
+    // A small "updated 40s ago" / "stale" / "error" note for the sources behind a panel
+    fn freshness(&self, ui: &mut egui::Ui, kind: scheduler::SourceKind) {
+        let mut sources = self.sources.values().filter(|h| h.kind == kind).peekable();
+        if sources.peek().is_none() {
+            return;
+        }
+
+        let now = self.clock.timestamp();
+        let (text, color) = match scheduler::Freshness::of(sources, now) {
+            scheduler::Freshness::Loading => match self.cached_at {
+                Some(t) => (
+                    format!("cached {} ago", style::age(now - t)),
+                    style::FG_MUTED,
+                ),
+                None => ("loading".to_string(), style::FG_MUTED),
+            },
+            scheduler::Freshness::Updated(t) => (
+                format!("updated {} ago", style::age(now - t)),
+                style::FG_MUTED,
+            ),
+            scheduler::Freshness::Stale => ("stale".to_string(), style::DANGER),
+            scheduler::Freshness::Error => ("error".to_string(), style::DANGER),
+        };
+        ui.add(egui::Label::new(
+            egui::RichText::new(text).small().color(color),
+        ));
     }
 
     fn footer(&self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             let mut frame = egui::Frame::none();
-            frame.margin = egui::Vec2::new(5.0, 5.0);
+            frame.margin = egui::Vec2::splat(self.layout.margin());
             frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
             frame.fill = style::FG;
             frame.show(ui, |ui| {
                 let desc = egui::Label::new(
-                    egui::RichText::new(&self.clock)
+                    egui::RichText::new(self.clock_text())
                         .monospace()
                         .color(egui::Color32::BLACK),
                 );
                 ui.add(desc);
             });
+
+            if !self.keyboard_connected {
+                ui.add_space(self.layout.gap());
+                ui.add(egui::Label::new(
+                    egui::RichText::new("keyboard disconnected")
+                        .monospace()
+                        .color(style::DANGER),
+                ));
+            }
+
+            if let Some(toast) = &self.toast {
+                ui.add_space(self.layout.gap());
+                ui.add(egui::Label::new(
+                    egui::RichText::new(&toast.text).monospace(),
+                ));
+            }
         });
     }
 
@@ -146,54 +530,112 @@ impl AppState {
 }
 
 impl App {
-    fn new() -> Self {
+    fn new(run: cli::Run) -> Self {
+        let mut state = AppState::new();
+        state.load_cache();
+        if run.dev {
+            state.config.input_backend = keyboard::Backend::Window;
+        }
+        if state.config.input_backend == keyboard::Backend::Window {
+            state.window_keys = Some(keyboard::Gestures::new(state.config.key_timing()));
+            state.keyboard_connected = true;
+        }
         Self {
-            data: Arc::new(Mutex::new(AppState::new())),
+            data: Arc::new(Mutex::new(state)),
         }
     }
 
     async fn start_async(&self) {
+        let (actions_tx, actions_rx) = tokio::sync::mpsc::unbounded_channel();
+        self.data.lock().unwrap().actions = Some(actions_tx);
+        let data = self.data.clone();
+        tokio::spawn(async move {
+            actions::run(data, actions_rx).await;
+        });
+
+        let data = self.data.clone();
+        tokio::spawn(async move {
+            control::serve(data).await;
+        });
+
+        let (desktop_tx, desktop_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mode = {
+            let mut state = self.data.lock().unwrap();
+            state.desktop = Some(desktop_tx);
+            state.config.desktop_notifications
+        };
         let data = self.data.clone();
         tokio::spawn(async move {
-            calendar::run(data).await;
+            desktop::run(data, mode, desktop_rx).await;
         });
+
         let data = self.data.clone();
         tokio::spawn(async move {
-            github::run(data).await;
+            webhook::serve(data).await;
+        });
+
+        let scheduler = scheduler::Scheduler::new(self.data.clone());
+        scheduler.add(calendar::Calendar::new(scheduler.clock()));
+        let config = self.data.lock().unwrap().config.clone();
+        tokio::spawn(async move {
+            github::register(&scheduler, &config).await;
+            gitlab::register(&scheduler, &config).await;
         });
 
         // Timer to refresh UI
-        let mut interval = tokio::time::interval(std::time::Duration::from_millis(200));
+        let mut interval = tokio::time::interval(HEARTBEAT);
         loop {
             interval.tick().await;
             let mut _data = self.data.lock().unwrap();
             if let Some(frame) = _data.frame.as_ref() {
                 frame.request_repaint();
             }
-            match _data.page {
-                PageState::Home(_) => _data.heartbeat_home(),
-                PageState::Shortcuts { .. } => (),
-                PageState::PullRequests { .. } => _data.heartbeat_pulls(),
-            };
-            _data.clock = AppState::clock_time();
+            _data.heartbeat(std::time::Instant::now());
+            _data.save_cache();
+            if matches!(&_data.toast, Some(t) if t.expires < std::time::Instant::now()) {
+                _data.toast = None;
+            }
         }
     }
 
     fn bindkeys(&self) {
+        if self.data.lock().unwrap().window_keys.is_some() {
+            return;
+        }
+
         let _self = self.clone();
         std::thread::spawn(move || {
-            keyboard::handle_input_events(move |key| {
-                let mut state = _self.data.lock().unwrap();
-                match state.page {
-                    PageState::Home(_) => state.handle_kbd_home(key),
-                    PageState::Shortcuts { .. } => state.handle_kbd_shortcuts(key),
-                    PageState::PullRequests(_) => state.handle_kbd_pull_requests(key),
-                };
-
-                if let Some(frame) = state.frame.as_ref() {
-                    frame.request_repaint();
+            let (timing, source) = {
+                let state = _self.data.lock().unwrap();
+                (state.config.key_timing(), state.config.input_source())
+            };
+            let source = match source {
+                Ok(source) => source,
+                Err(e) => {
+                    eprintln!("not reading keys: {e}");
+                    return;
                 }
-            });
+            };
+            let status = _self.clone();
+            keyboard::handle_input_events(
+                keymap::Keymap::load(),
+                timing,
+                &source,
+                move |event| {
+                    _self
+                        .data
+                        .lock()
+                        .unwrap()
+                        .handle_key_event(event, std::time::Instant::now());
+                },
+                move |connected| {
+                    let mut state = status.data.lock().unwrap();
+                    state.keyboard_connected = connected;
+                    if let Some(frame) = state.frame.as_ref() {
+                        frame.request_repaint();
+                    }
+                },
+            );
         });
     }
 }
@@ -209,42 +651,52 @@ impl epi::App for App {
         frame: &epi::Frame,
         _storage: Option<&dyn epi::Storage>,
     ) {
-        // Create a font definition object
-        let mut font_def = FontDefinitions::default();
-        font_def
-            .family_and_size
-            .insert(egui::TextStyle::Heading, (FontFamily::Proportional, 24.));
-
-        font_def
-            .family_and_size
-            .insert(egui::TextStyle::Monospace, (FontFamily::Monospace, 24.));
-        // Set the size of text styles
-        // Load the font using ctx
-        ctx.set_fonts(font_def);
-
         let mut app_data = self.data.lock().unwrap();
+        ctx.set_fonts(app_data.layout.fonts());
         app_data.frame = Some(frame.clone());
     }
 
     fn update(&mut self, ctx: &egui::CtxRef, _frame: &epi::Frame) {
+        self.draw(ctx);
+    }
+}
+
+impl App {
+    // Everything update draws, split out so it can run without a window
+    fn draw(&mut self, ctx: &egui::CtxRef) {
+        {
+            let mut app_data = self.data.lock().unwrap();
+            app_data.window_input(ctx);
+
+            // Fonts set now apply from the next frame, and are measured then
+            let size = ctx.input().screen_rect().size();
+            let layout = layout::Layout::new(size, app_data.config.ui_scale);
+            if layout.scale != app_data.layout.scale {
+                ctx.set_fonts(layout.fonts());
+            }
+            app_data.layout = layout.measure(ctx.fonts());
+        }
+
+        let mut clicked = None;
+        let panel_width = self.data.lock().unwrap().layout.panel_width();
         egui::SidePanel::right("right_panel")
             .frame(egui::Frame::none().fill(style::BG))
-            .default_width(30.0)
-            .max_width(30.0)
-            .min_width(30.0)
+            .default_width(panel_width)
+            .max_width(panel_width)
+            .min_width(panel_width)
             .show(ctx, |ui| {
                 let app_data = self.data.lock().unwrap();
+                let layout = app_data.layout;
 
                 let spacing = ui.available_height() / 4.0;
 
-                for cmd in app_data.commands() {
-                    ui.allocate_ui(egui::Vec2::new(20.0, spacing), |ui| {
-                        let padding = 10.0;
-                        let text_space = cmd.name.len() as f32 * 25.0 + 2.0 * padding;
+                for (idx, cmd) in app_data.commands().into_iter().enumerate() {
+                    let cell = ui.allocate_ui(egui::Vec2::new(panel_width, spacing), |ui| {
+                        let text_space = layout.command_height(cmd.name);
 
                         ui.add_space((spacing - text_space) / 2.0);
                         let mut frame = egui::Frame::none();
-                        frame.margin = egui::Vec2::new(5.0, padding);
+                        frame.margin = egui::Vec2::new(layout.margin(), layout.gap());
                         if cmd.selected {
                             frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
                         };
@@ -255,16 +707,36 @@ impl epi::App for App {
                         });
                         ui.add_space((spacing - text_space) / 2.0);
                     });
+
+                    // The cells would otherwise share an ID, and clash
+                    let click =
+                        ui.interact(cell.response.rect, ui.id().with(idx), egui::Sense::click());
+                    if app_data.window_keys.is_some() && click.clicked() {
+                        clicked = Some(idx);
+                    }
                 }
             });
 
+        let lcd = [
+            keyboard::Key::LCD1,
+            keyboard::Key::LCD2,
+            keyboard::Key::LCD3,
+            keyboard::Key::LCD4,
+        ];
+        if let Some(key) = clicked.and_then(|idx| lcd.get(idx)) {
+            self.data.lock().unwrap().window_tap(*key);
+        }
+
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(style::BG))
             .show(ctx, |ui| {
                 let app_data = self.data.lock().unwrap();
 
                 let (rect, _) = ui.allocate_exact_size(
-                    egui::Vec2::new(ui.available_width(), ui.available_height() - 37.0),
+                    egui::Vec2::new(
+                        ui.available_width(),
+                        ui.available_height() - app_data.layout.footer_height(),
+                    ),
                     egui::Sense::hover(),
                 );
                 app_data.footer(ui);
@@ -284,9 +756,17 @@ impl epi::App for App {
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let run = match cli::run(&args).await {
+        Some(run) => run,
+        None => return,
+    };
+
     let options = eframe::NativeOptions::default();
 
-    let app = App::new();
+    credentials::setup(&config::Config::load()).await;
+
+    let app = App::new(run);
     app.bindkeys();
 
     let _app = app.clone();
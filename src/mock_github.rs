@@ -0,0 +1,136 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// A stand-in for the GitHub or GitLab API that answers from recorded responses.
+// Point an account's api_url (or url, for GitLab) at it to see how the console
+// handles pagination, 304s, bad credentials, rate limits and malformed payloads
+// without touching the real thing.
+//
+// A fixture is a list of routes, each matched on method and path with query. A
+// route's responses are served in order and the last one repeats, so a list can
+// be answered once and then be "not modified". "{base}" in headers and bodies is
+// replaced with the server's own URL, for Link headers.
+#[derive(Deserialize)]
+struct Route {
+    method: String,
+    path: String,
+    responses: Vec<Reply>,
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+    // Sent as-is instead of body, for payloads that aren't JSON at all
+    #[serde(default)]
+    raw: Option<String>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+struct Routes {
+    routes: Vec<Route>,
+    served: Vec<usize>,
+}
+
+#[cfg(feature = "dev")]
+pub async fn serve(fixture: &std::path::Path, listen: &str) -> Result<(), String> {
+    let (addr, server) = bind(fixture, listen)?;
+    println!("serving {} on http://{addr}", fixture.display());
+    server.await
+}
+
+// Starts listening straight away, so port 0 can be used to pick a free port, and
+// returns the address along with the server to run
+pub fn bind(
+    fixture: &std::path::Path,
+    listen: &str,
+) -> Result<
+    (
+        std::net::SocketAddr,
+        impl std::future::Future<Output = Result<(), String>>,
+    ),
+    String,
+> {
+    let contents = std::fs::read_to_string(fixture)
+        .map_err(|e| format!("couldn't read {}: {e}", fixture.display()))?;
+    let routes: Vec<Route> =
+        serde_json::from_str(&contents).map_err(|e| format!("{}: {e}", fixture.display()))?;
+    if let Some(route) = routes.iter().find(|r| r.responses.is_empty()) {
+        return Err(format!("{} {} has no responses", route.method, route.path));
+    }
+
+    let listener = std::net::TcpListener::bind(listen)
+        .and_then(|l| l.set_nonblocking(true).map(|_| l))
+        .map_err(|e| format!("couldn't listen on {listen}: {e}"))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let base = Arc::new(format!("http://{addr}"));
+    let routes = Arc::new(Mutex::new(Routes {
+        served: vec![0; routes.len()],
+        routes,
+    }));
+
+    let make_svc = make_service_fn(move |_| {
+        let routes = routes.clone();
+        let base = base.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(service_fn(move |req| {
+                let response = handle(&routes, &base, req);
+                async move { response }
+            }))
+        }
+    });
+
+    let server = hyper::Server::from_tcp(listener)
+        .map_err(|e| format!("couldn't listen on {addr}: {e}"))?
+        .serve(make_svc);
+    Ok((addr, async move { server.await.map_err(|e| e.to_string()) }))
+}
+
+fn handle(
+    routes: &Mutex<Routes>,
+    base: &str,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::http::Error> {
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let mut routes = routes.lock().unwrap();
+    let Routes { routes, served } = &mut *routes;
+
+    let idx = routes
+        .iter()
+        .position(|r| r.method.eq_ignore_ascii_case(req.method().as_str()) && r.path == path);
+    let reply = match idx {
+        Some(idx) => {
+            let responses = &routes[idx].responses;
+            let reply = &responses[served[idx].min(responses.len() - 1)];
+            served[idx] += 1;
+            reply
+        }
+        None => {
+            eprintln!("{} {path} -> 404 (no route)", req.method());
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(r#"{"message":"Not Found"}"#));
+        }
+    };
+
+    let mut response = Response::builder().status(reply.status);
+    for (name, value) in &reply.headers {
+        response = response.header(name.as_str(), value.replace("{base}", base));
+    }
+    let body = match (&reply.raw, &reply.body) {
+        (Some(raw), _) => raw.clone(),
+        (None, Some(body)) => body.to_string().replace("{base}", base),
+        (None, None) => String::new(),
+    };
+    response.body(Body::from(body))
+}
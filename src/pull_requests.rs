@@ -1,103 +1,215 @@
+use crate::actions::{ActionRequest, PrAction};
+use serde::{Deserialize, Serialize};
+
 use crate::command;
 use crate::keyboard;
+use crate::scheduler::SourceKind;
 use crate::style;
-use crate::{AppState, Command, PageState, PullRequest};
+use crate::{
+    AppState, CheckState, Command, ListCursor, PageState, PrState, PullRequest, ReviewDecision,
+};
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Serialize, Deserialize)]
 pub enum Page {
     InProgress,
     Submitted,
     Review,
 }
 
+// How long an armed action waits for Execute before it is cancelled
+const ARM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub struct Armed {
+    request: ActionRequest,
+    since: std::time::Instant,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct PullRequestsState {
     page: Page,
-    selected: usize,
-    scroll: usize,
-    slider: usize,
+    #[serde(default)]
+    cursor: ListCursor,
+    drafts: bool,
+    // Destructive actions are never restored from the cache
+    #[serde(skip)]
+    danger: Option<std::time::Instant>,
+    #[serde(skip)]
+    armed: Option<Armed>,
 }
 
 impl PullRequestsState {
     pub fn default() -> Self {
         Self {
             page: Page::InProgress,
-            selected: 0,
-            scroll: 0,
-            slider: 0,
+            cursor: ListCursor::default(),
+            drafts: false,
+            danger: None,
+            armed: None,
+        }
+    }
+
+    // What the LCD keys can arm for the selected PR
+    fn danger_actions(&self, pr: Option<&PullRequest>) -> [Option<PrAction>; 4] {
+        match self.page {
+            Page::InProgress if self.drafts => {
+                [Some(PrAction::Ready), None, Some(PrAction::Close), None]
+            }
+            Page::InProgress => [
+                pr.filter(|pr| ready_to_merge(pr)).map(|_| PrAction::Merge),
+                Some(PrAction::Rerun),
+                Some(PrAction::Close),
+                None,
+            ],
+            Page::Review => [Some(PrAction::Approve), Some(PrAction::Rerun), None, None],
+            Page::Submitted => [None, None, None, None],
         }
     }
+
+    fn arm(&mut self, slot: usize, target: Option<&PullRequest>) {
+        if let (Some(action), Some(pr)) = (self.danger_actions(target)[slot], target) {
+            self.armed = Some(Armed {
+                request: ActionRequest {
+                    action,
+                    owner: pr.owner.clone(),
+                    repo: pr.repo_name.clone(),
+                    number: pr.number,
+                    provider_id: pr.provider_id.clone(),
+                    account: pr.account.clone(),
+                },
+                since: std::time::Instant::now(),
+            });
+        }
+        self.danger = None;
+    }
+
+    fn disarm(&mut self) {
+        self.danger = None;
+        self.armed = None;
+    }
+}
+
+// Only approved PRs with passing checks are offered for merging
+fn ready_to_merge(pr: &PullRequest) -> bool {
+    pr.state == PrState::Open
+        && pr.review == Some(ReviewDecision::Approved)
+        && pr.checks == Some(CheckState::Success)
 }
 
 impl AppState {
     pub fn handle_kbd_pull_requests(&mut self, key: keyboard::Key) {
         let num_pulls = self.get_pulls().len();
+        let target = self.selected_pull().cloned();
         let mut s = match &mut self.page {
             PageState::PullRequests(s) => s,
             _ => unreachable!("wrong page!"),
         };
 
+        // Danger mode: the LCD keys pick a destructive action to arm
+        if s.danger.is_some() {
+            match key {
+                keyboard::Key::LCD1 => s.arm(0, target.as_ref()),
+                keyboard::Key::LCD2 => s.arm(1, target.as_ref()),
+                keyboard::Key::LCD3 => s.arm(2, target.as_ref()),
+                keyboard::Key::LCD4 => s.arm(3, target.as_ref()),
+                _ => s.disarm(),
+            }
+            return;
+        }
+
+        if let Some(armed) = s.armed.take() {
+            // Any key other than Execute cancels the armed action
+            if !matches!(key, keyboard::Key::Execute) {
+                return;
+            }
+
+            let request = armed.request;
+            // The PR may have changed since the merge was armed
+            let current = self.open_prs.iter().find(|pr| {
+                pr.account == request.account
+                    && pr.provider_id == request.provider_id
+                    && pr.number == request.number
+            });
+            if request.action == PrAction::Merge && !current.is_some_and(ready_to_merge) {
+                self.show_toast("not approved and green".to_string());
+                return;
+            }
+            let text = format!(
+                "{} {}#{}...",
+                request.action.label(),
+                request.repo,
+                request.number
+            );
+            match &self.actions {
+                Some(tx) if tx.send(request).is_ok() => self.show_toast(text),
+                _ => self.show_toast("actions unavailable".to_string()),
+            }
+            return;
+        }
+
         match key {
+            keyboard::Key::Danger
+                if s.danger_actions(target.as_ref())
+                    .iter()
+                    .any(|a| a.is_some()) =>
+            {
+                s.danger = Some(std::time::Instant::now());
+            }
             keyboard::Key::LCD1 => {
                 // In progress
                 s.page = Page::InProgress;
+                s.drafts = false;
             }
             keyboard::Key::LCD2 => {
                 // Submitted
                 s.page = Page::Submitted;
+                s.drafts = false;
             }
             keyboard::Key::LCD3 => {
                 s.page = Page::Review;
+                s.drafts = false;
+            }
+            keyboard::Key::LCD4 => {
+                // Toggle between ready and draft in-progress PRs
+                s.drafts = s.page != Page::InProgress || !s.drafts;
+                s.page = Page::InProgress;
             }
             keyboard::Key::Abort => self.page = PageState::home(),
             keyboard::Key::Execute => {
                 // Execute selected thingy
-                let selected = s.selected + s.scroll;
-                if let Some(item) = self.get_pulls().get(selected) {
+                if let Some(item) = target {
                     command::open_url(&item.url);
                 }
             }
-            keyboard::Key::Slider(pos) => {
-                s.slider = 5 - pos;
-
-                if s.slider == 0 {
-                    if s.scroll > 0 {
-                        s.scroll -= 1;
-                    } else {
-                        s.selected = 0;
-                    }
-                } else if s.slider < 5 {
-                    s.selected = s.slider;
-                } else if s.slider == 5 {
-                    s.selected = 5;
-
-                    if s.scroll >= num_pulls - 5 && s.selected < num_pulls {
-                        s.selected += 1;
-                    }
-
-                    if s.scroll >= num_pulls - 5 {
-                        s.scroll += 1;
-                    }
-                }
+            keyboard::Key::Slider(_) => {
+                s.cursor
+                    .moved(&self.slider, num_pulls, self.layout.list_rows())
             }
             _ => (),
         }
     }
 
-    pub fn heartbeat_pulls(&mut self) {
+    pub fn heartbeat_pulls(&mut self, now: std::time::Instant) {
+        let num_pulls = self.get_pulls().len();
         let mut s = match &mut self.page {
             PageState::PullRequests(s) => s,
             _ => unreachable!("wrong page!"),
         };
 
-        if s.slider == 0 {
-            if s.scroll > 0 {
-                s.scroll -= 1;
-            } else if s.selected > 0 {
-                s.selected -= 1;
-            }
-        } else if s.slider == 5 {
-            s.scroll += 1;
+        let since = s.armed.as_ref().map(|a| a.since).or(s.danger);
+        if matches!(since, Some(t) if now.saturating_duration_since(t) > ARM_TIMEOUT) {
+            s.disarm();
         }
+
+        s.cursor
+            .tick(&self.slider, num_pulls, self.layout.list_rows(), now);
+    }
+
+    fn selected_pull(&self) -> Option<&PullRequest> {
+        let s = match &self.page {
+            PageState::PullRequests(s) => s,
+            _ => unreachable!("wrong page!"),
+        };
+        self.get_pulls().get(s.cursor.index())
     }
 
     pub fn get_pulls(&self) -> &[PullRequest] {
@@ -106,9 +218,10 @@ impl AppState {
             _ => unreachable!("wrong page!"),
         };
         match s.page {
+            Page::InProgress if s.drafts => self.draft_prs.as_slice(),
             Page::InProgress => self.open_prs.as_slice(),
             Page::Submitted => self.closed_prs.as_slice(),
-            Page::Review => &[],
+            Page::Review => self.review_prs.as_slice(),
         }
     }
 
@@ -118,46 +231,136 @@ impl AppState {
             _ => unreachable!("wrong page!"),
         };
         let mut frame = egui::Frame::none();
-        frame.margin = egui::Vec2::new(20.0, 20.0);
+        frame.margin = egui::Vec2::splat(self.layout.page_margin());
         frame.show(ui, |ui| {
-            let clip_rect = ui.max_rect().expand(5.0);
+            let clip_rect = ui.max_rect().expand(self.layout.margin());
             ui.set_clip_rect(clip_rect);
 
-            for (idx, pr) in self.get_pulls().iter().skip(s.scroll).enumerate() {
+            self.freshness(ui, SourceKind::PullRequests);
+            ui.add_space(self.layout.margin());
+
+            if let Some(armed) = &s.armed {
+                let remaining = ARM_TIMEOUT.saturating_sub(armed.since.elapsed());
                 ui.horizontal(|ui| {
                     let mut frame = egui::Frame::none();
-                    frame.margin = egui::Vec2::new(5.0, 5.0);
-                    frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
+                    frame.margin = egui::Vec2::splat(self.layout.margin());
+                    frame = frame.stroke(egui::Stroke::new(style::STROKE, style::DANGER));
+                    frame.fill = style::DANGER;
+                    frame.show(ui, |ui| {
+                        ui.add(egui::Label::new(
+                            egui::RichText::new("ARMED").monospace().color(style::BG),
+                        ));
+                    });
 
-                    if idx == s.selected {
-                        frame.fill = style::FG;
+                    ui.add_space(self.layout.gap());
+                    ui.add(egui::Label::new(
+                        egui::RichText::new(format!(
+                            "{} {}#{} ({}s)",
+                            armed.request.action.label(),
+                            armed.request.repo,
+                            armed.request.number,
+                            remaining.as_secs() + 1
+                        ))
+                        .color(style::DANGER)
+                        .heading(),
+                    ));
+                });
+                ui.add_space(self.layout.gap());
+            }
+
+            let rows = self.layout.list_rows();
+            for (idx, pr) in self
+                .get_pulls()
+                .iter()
+                .skip(s.cursor.scroll)
+                .take(rows)
+                .enumerate()
+            {
+                let color = match pr.state {
+                    PrState::Merged => style::MERGED,
+                    PrState::Closed => style::DANGER,
+                    PrState::Draft => style::FG_MUTED,
+                    PrState::Open => style::FG,
+                };
+
+                ui.horizontal(|ui| {
+                    let mut frame = egui::Frame::none();
+                    frame.margin = egui::Vec2::splat(self.layout.margin());
+                    frame = frame.stroke(egui::Stroke::new(style::STROKE, color));
+
+                    if idx == s.cursor.selected {
+                        frame.fill = color;
                     }
                     frame.show(ui, |ui| {
                         let desc = egui::Label::new(
-                            egui::RichText::new(style::eta(pr.updated_at))
-                                .monospace()
-                                .color(if idx == s.selected {
-                                    style::BG
-                                } else {
-                                    style::FG
-                                }),
+                            egui::RichText::new(style::eta(
+                                pr.closed_at.unwrap_or(pr.updated_at),
+                                self.clock.timestamp(),
+                            ))
+                            .monospace()
+                            .color(if idx == s.cursor.selected {
+                                style::BG
+                            } else {
+                                color
+                            }),
                         );
                         ui.add(desc);
                     });
 
-                    ui.add_space(10.0);
-                    ui.add(egui::Label::new(egui::RichText::new(&pr.title).heading()));
+                    ui.add_space(self.layout.gap());
+                    let mut title = egui::RichText::new(&pr.title).heading();
+                    if pr.state == PrState::Draft {
+                        title = title.color(style::FG_MUTED);
+                    }
+                    ui.add(egui::Label::new(title));
                 });
                 ui.horizontal(|ui| {
                     ui.add(egui::Label::new(
-                        egui::RichText::new(&pr.repo_name)
-                            .color(style::FG_MUTED)
-                            .heading(),
+                        egui::RichText::new(format!(
+                            "{}{}",
+                            self.account_tag(&pr.account),
+                            pr.repo_name
+                        ))
+                        .color(style::FG_MUTED)
+                        .heading(),
                     ));
+
+                    let checks = match pr.checks {
+                        Some(CheckState::Success) => Some(("CI OK", style::FG)),
+                        Some(CheckState::Failure) => Some(("CI FAIL", style::DANGER)),
+                        Some(CheckState::Pending) => Some(("CI ...", style::FG_MUTED)),
+                        None => None,
+                    };
+                    let review = match pr.review {
+                        Some(ReviewDecision::Approved) => Some(("APPROVED", style::FG)),
+                        Some(ReviewDecision::ChangesRequested) => Some(("CHANGES", style::DANGER)),
+                        Some(ReviewDecision::ReviewRequired) | None => None,
+                    };
+                    let state = match pr.state {
+                        PrState::Draft => Some(("DRAFT", style::FG_MUTED)),
+                        PrState::Merged => Some(("MERGED", style::MERGED)),
+                        PrState::Closed => Some(("CLOSED", style::DANGER)),
+                        PrState::Open => None,
+                    };
+                    for (text, color) in state.into_iter().chain(checks).chain(review) {
+                        ui.add_space(self.layout.gap());
+                        ui.add(egui::Label::new(
+                            egui::RichText::new(text).monospace().color(color),
+                        ));
+                    }
+
+                    if pr.comments > 0 {
+                        ui.add_space(self.layout.gap());
+                        ui.add(egui::Label::new(
+                            egui::RichText::new(format!("{} comments", pr.comments))
+                                .color(style::FG_MUTED)
+                                .heading(),
+                        ));
+                    }
                 });
             }
 
-            ui.add_space(10.0);
+            ui.add_space(self.layout.gap());
         });
     }
 
@@ -167,10 +370,24 @@ impl AppState {
             _ => unreachable!("wrong page!"),
         };
 
+        if s.danger.is_some() || s.armed.is_some() {
+            return s
+                .danger_actions(self.selected_pull())
+                .iter()
+                .map(|action| match action {
+                    Some(a) => Command {
+                        name: a.label(),
+                        selected: matches!(&s.armed, Some(armed) if armed.request.action == *a),
+                    },
+                    None => Command::empty(),
+                })
+                .collect();
+        }
+
         vec![
             Command {
                 name: "IPR",
-                selected: s.page == Page::InProgress,
+                selected: s.page == Page::InProgress && !s.drafts,
             },
             Command {
                 name: "SUB",
@@ -180,7 +397,69 @@ impl AppState {
                 name: "REV",
                 selected: s.page == Page::Review,
             },
-            Command::empty(),
+            Command {
+                name: "DRFT",
+                selected: s.page == Page::InProgress && s.drafts,
+            },
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr(checks: Option<CheckState>, review: Option<ReviewDecision>) -> PullRequest {
+        PullRequest {
+            provider_id: "PR_kwDOABC12".to_string(),
+            title: "Add a layout engine".to_string(),
+            url: "https://github.com/colin353/console-gui/pull/12".to_string(),
+            updated_at: 1714557000,
+            repo_name: "console-gui".to_string(),
+            owner: "colin353".to_string(),
+            number: 12,
+            account: "github".to_string(),
+            state: PrState::Open,
+            closed_at: None,
+            checks,
+            review,
+            comments: 0,
+        }
+    }
+
+    #[test]
+    fn merge_needs_approval_and_green_checks() {
+        let s = PullRequestsState::default();
+        let merge = |pr: PullRequest| s.danger_actions(Some(&pr))[0];
+
+        let ready = pr(Some(CheckState::Success), Some(ReviewDecision::Approved));
+        assert_eq!(merge(ready), Some(PrAction::Merge));
+        assert_eq!(merge(pr(Some(CheckState::Success), None)), None);
+        assert_eq!(
+            merge(pr(
+                Some(CheckState::Pending),
+                Some(ReviewDecision::Approved)
+            )),
+            None
+        );
+        assert_eq!(merge(pr(None, Some(ReviewDecision::Approved))), None);
+        assert_eq!(s.danger_actions(None)[0], None);
+    }
+
+    #[test]
+    fn replays_recording() {
+        let mut state = crate::recording::fixture_state(PageState::pull_requests());
+        crate::recording::replay_fixture("pull_requests.jsonl", &mut state);
+        let s = match &state.page {
+            PageState::PullRequests(s) => s,
+            _ => panic!("left the pull requests page"),
+        };
+        assert!(s.page == Page::Submitted);
+        // Drafts are only shown on the in progress tab
+        assert!(!s.drafts);
+        assert_eq!(s.cursor.index(), 1);
+        // Nothing on the submitted tab is destructive, so danger mode never starts
+        assert!(s.danger.is_none());
+        assert_eq!(state.selected_pull().map(|pr| pr.number), Some(9));
+    }
+}
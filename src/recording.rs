@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use crate::keyboard::KeyEvent;
+use crate::AppState;
+
+// Key event streams saved as JSON lines, each with the milliseconds since recording
+// started, so a session can be played back with its original timing.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    ms: u64,
+    event: KeyEvent,
+}
+
+pub struct Recorder {
+    out: std::io::BufWriter<std::fs::File>,
+    start: std::time::Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &std::path::Path) -> Result<Self, String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self {
+            out: std::io::BufWriter::new(file),
+            start: std::time::Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: KeyEvent) {
+        let entry = Entry {
+            ms: self.start.elapsed().as_millis() as u64,
+            event,
+        };
+        // Flush every line, so the recording survives the app being killed
+        let result = serde_json::to_writer(&mut self.out, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.out.write_all(b"\n"))
+            .and_then(|_| self.out.flush());
+        if let Err(e) = result {
+            eprintln!("couldn't record key event: {:?}", e);
+        }
+    }
+}
+
+pub fn load(path: &std::path::Path) -> Result<Vec<(std::time::Duration, KeyEvent)>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            let entry: Entry = serde_json::from_str(line)
+                .map_err(|e| format!("{}:{}: {e}", path.display(), n + 1))?;
+            Ok((std::time::Duration::from_millis(entry.ms), entry.event))
+        })
+        .collect()
+}
+
+// Feeds a recording straight into the app, as if it had started just now, with
+// the heartbeat ticking in between events
+pub fn replay(path: &std::path::Path, state: &mut AppState) -> Result<(), String> {
+    let start = std::time::Instant::now();
+    let mut ticked = std::time::Duration::ZERO;
+    for (at, event) in load(path)? {
+        while ticked + crate::HEARTBEAT <= at {
+            ticked += crate::HEARTBEAT;
+            state.heartbeat(start + ticked);
+        }
+        state.handle_key_event(event, start + at);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn fixtures() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+// The app as saved in the shared cache fixture, on the given page
+#[cfg(test)]
+pub fn fixture_state(page: crate::PageState) -> AppState {
+    let mut state = AppState::with_config(crate::config::Config::default());
+    state
+        .load_cache_from(&fixtures().join("cache.json"))
+        .unwrap();
+    state.page = page;
+    state
+}
+
+#[cfg(test)]
+pub fn replay_fixture(recording: &str, state: &mut AppState) {
+    replay(&fixtures().join("recordings").join(recording), state).unwrap();
+}
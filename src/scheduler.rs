@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use crate::clock::SharedClock;
+use crate::AppState;
+
+// A background poller: fetches data on an interval and merges it into the app state
+#[async_trait::async_trait]
+pub trait DataSource: Send + 'static {
+    type Output: Send;
+
+    fn name(&self) -> String;
+
+    fn interval(&self) -> std::time::Duration;
+
+    // Which panel shows this source's data
+    fn kind(&self) -> SourceKind;
+
+    // How old the last successful fetch can get before the data is considered stale
+    fn stale_after(&self) -> std::time::Duration {
+        self.interval() * 3
+    }
+
+    async fn fetch(&mut self) -> Result<Self::Output, String>;
+
+    fn merge(&self, output: Self::Output, state: &mut AppState);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceKind {
+    Calendar,
+    Notifications,
+    PullRequests,
+}
+
+pub struct SourceHealth {
+    pub kind: SourceKind,
+    pub last_success: Option<i64>,
+    pub last_error: Option<(i64, String)>,
+    pub stale_after: std::time::Duration,
+}
+
+pub enum Freshness {
+    Loading,
+    Updated(i64),
+    Stale,
+    Error,
+}
+
+impl Freshness {
+    // Summarise the sources feeding one panel, reporting the worst of them
+    pub fn of<'a>(sources: impl Iterator<Item = &'a SourceHealth>, now: i64) -> Self {
+        let mut freshness = None;
+        for health in sources {
+            let this = match (health.last_success, &health.last_error) {
+                (_, Some(_)) => Self::Error,
+                (None, None) => Self::Loading,
+                (Some(t), None) if now - t > health.stale_after.as_secs() as i64 => Self::Stale,
+                (Some(t), None) => Self::Updated(t),
+            };
+
+            freshness = Some(match (freshness, this) {
+                (None, this) => this,
+                (Some(Self::Error), _) | (_, Self::Error) => Self::Error,
+                (Some(Self::Stale), _) | (_, Self::Stale) => Self::Stale,
+                (Some(Self::Loading), _) | (_, Self::Loading) => Self::Loading,
+                (Some(Self::Updated(a)), Self::Updated(b)) => Self::Updated(a.min(b)),
+            });
+        }
+        freshness.unwrap_or(Self::Loading)
+    }
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    data: Arc<Mutex<AppState>>,
+    clock: SharedClock,
+}
+
+impl Scheduler {
+    pub fn new(data: Arc<Mutex<AppState>>) -> Self {
+        let clock = data.lock().unwrap().clock.clone();
+        Self { data, clock }
+    }
+
+    // The app's clock, for sources whose queries depend on the time
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    pub fn add<S: DataSource>(&self, mut source: S) {
+        let name = source.name();
+        let mut refresh = {
+            let mut state = self.data.lock().unwrap();
+            state.sources.insert(
+                name.clone(),
+                SourceHealth {
+                    kind: source.kind(),
+                    last_success: None,
+                    last_error: None,
+                    stale_after: source.stale_after(),
+                },
+            );
+            state.refresh.subscribe()
+        };
+
+        let data = self.data.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(source.interval());
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = refresh.changed() => {
+                        // A refresh can be aimed at one account's sources
+                        let wanted = match &*refresh.borrow() {
+                            Some(account) => name.starts_with(&format!("{account}/")),
+                            None => true,
+                        };
+                        if !wanted {
+                            continue;
+                        }
+                        interval.reset();
+                    }
+                }
+
+                let result = source.fetch().await;
+
+                let mut state = data.lock().unwrap();
+                let now = clock.timestamp();
+                match result {
+                    Ok(output) => {
+                        source.merge(output, &mut state);
+                        state.cache_dirty = true;
+                        if let Some(health) = state.sources.get_mut(&name) {
+                            health.last_success = Some(now);
+                            health.last_error = None;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{name}: {e}");
+                        if let Some(health) = state.sources.get_mut(&name) {
+                            health.last_error = Some((now, e));
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
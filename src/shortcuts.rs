@@ -39,7 +39,10 @@ impl AppState {
                                 eprintln!("unable to start zoom! {:?}", e);
                             }
                         }
-                        1 => println!("something"),
+                        1 => {
+                            self.refresh_all();
+                            self.show_toast("refreshing".to_string());
+                        }
                         _ => println!("unknown!"),
                     }
                     self.page = PageState::Shortcuts { selected: None };
@@ -96,7 +99,7 @@ impl AppState {
     pub fn render_shortcuts(&self, ui: &mut egui::Ui) {
         let shortcuts = &[
             &["fix-video.sh", "zoom personal room", "screenshot"],
-            &["slack", "something", "shutdown"],
+            &["slack", "refresh", "shutdown"],
         ];
 
         let chunk_size = ui.available_height() / 4.0;
@@ -133,7 +136,7 @@ impl AppState {
 
                     for (idx, shortcut) in shortcut_chunk.iter().enumerate() {
                         ui.horizontal(|ui| {
-                            ui.add_space(39.);
+                            ui.add_space(self.layout.indent());
 
                             let mut frame = egui::Frame::none();
                             frame = frame.stroke(egui::Stroke::new(style::STROKE, style::FG));
@@ -158,7 +161,7 @@ impl AppState {
                             });
 
                             ui.heading(shortcut.to_string());
-                            ui.add_space(ui.available_width() - 10.);
+                            ui.add_space(ui.available_width() - self.layout.gap());
                         });
                     }
 
@@ -168,3 +171,19 @@ impl AppState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_recording() {
+        let mut state = crate::recording::fixture_state(PageState::shortcuts());
+        crate::recording::replay_fixture("shortcuts.jsonl", &mut state);
+        // The first group is opened, backed out of, then the third opened
+        assert!(matches!(
+            state.page,
+            PageState::Shortcuts { selected: Some(2) }
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+use eframe::egui;
+use eframe::egui::epaint::{ClippedShape, Color32, Shape};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::FixedClock;
+use crate::{config, control, recording, App, AppState};
+
+struct Options {
+    page: Option<control::Page>,
+    events: Option<std::path::PathBuf>,
+    size: egui::Vec2,
+    now: Option<FixedClock>,
+    expect: Option<std::path::PathBuf>,
+    update: bool,
+}
+
+fn parse(args: &[&str]) -> Result<Options, String> {
+    let mut options = Options {
+        page: None,
+        events: None,
+        size: egui::Vec2::new(800.0, 480.0),
+        now: None,
+        expect: None,
+        update: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match *arg {
+            "--page" => options.page = Some(control::Page::parse(value()?)?),
+            "--events" => options.events = Some(value()?.into()),
+            "--expect" => options.expect = Some(value()?.into()),
+            "--now" => options.now = Some(FixedClock::parse(value()?)?),
+            "--update" => options.update = true,
+            "--size" => {
+                let size = value()?;
+                let (w, h) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or(format!("bad size {size}, expected e.g. 800x480"))?;
+                options.size = egui::Vec2::new(w, h);
+            }
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    Ok(options)
+}
+
+// Renders a fixture (a cache file) without a window and describes what was drawn,
+// one shape per line, so page layouts can be checked against a saved snapshot
+pub fn run(fixture: &str, args: &[&str]) -> Result<(), String> {
+    let options = parse(args)?;
+    let mut state = load(std::path::Path::new(fixture), options.now)?;
+    if let Some(page) = options.page {
+        state.page = page.state();
+    }
+    let out = render(state, options.events.as_deref(), options.size)?;
+
+    let path = match &options.expect {
+        Some(path) => path,
+        None => {
+            print!("{out}");
+            return Ok(());
+        }
+    };
+    if options.update || !path.exists() {
+        std::fs::write(path, &out).map_err(|e| format!("{}: {e}", path.display()))?;
+        println!("wrote {}", path.display());
+        return Ok(());
+    }
+
+    let expected = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    compare(&expected, &out).map_err(|e| format!("{} {e}", path.display()))
+}
+
+fn load(fixture: &std::path::Path, now: Option<FixedClock>) -> Result<AppState, String> {
+    let mut state = AppState::with_config(config::Config::default());
+    state.load_cache_from(fixture)?;
+    state.keyboard_connected = true;
+    // Times are shown relative to now, so pin it: by default to when the fixture was saved
+    let clock = match now {
+        Some(clock) => clock,
+        None => FixedClock::at(state.cached_at.unwrap_or(0)),
+    };
+    state.clock = Arc::new(clock);
+    Ok(state)
+}
+
+fn render(
+    mut state: AppState,
+    events: Option<&std::path::Path>,
+    size: egui::Vec2,
+) -> Result<String, String> {
+    if let Some(path) = events {
+        recording::replay(path, &mut state)?;
+    }
+
+    let fonts = state.layout.fonts();
+    let mut app = App {
+        data: Arc::new(Mutex::new(state)),
+    };
+    let mut ctx = egui::CtxRef::default();
+    ctx.set_fonts(fonts);
+
+    // Fonts and panel sizes settle during the first frame, so describe the second
+    let mut shapes = Vec::new();
+    for _ in 0..2 {
+        ctx.begin_frame(egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, size)),
+            pixels_per_point: Some(1.0),
+            ..Default::default()
+        });
+        app.draw(&ctx);
+        shapes = ctx.end_frame().1;
+    }
+
+    let mut out = String::new();
+    for ClippedShape(_, shape) in &shapes {
+        describe(shape, &mut out);
+    }
+    Ok(out)
+}
+
+// Points at the first line that differs
+fn compare(expected: &str, actual: &str) -> Result<(), String> {
+    if expected == actual {
+        return Ok(());
+    }
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        let (e, a) = (expected_lines.next(), actual_lines.next());
+        if e != a {
+            return Err(format!(
+                "differs at line {line}\n  expected: {}\n  actual:   {}",
+                e.unwrap_or("(end)"),
+                a.unwrap_or("(end)"),
+            ));
+        }
+    }
+    unreachable!()
+}
+
+fn describe(shape: &Shape, out: &mut String) {
+    match shape {
+        Shape::Vec(shapes) => {
+            for shape in shapes {
+                describe(shape, out);
+            }
+        }
+        Shape::Text(text) => {
+            let color = text
+                .override_text_color
+                .or_else(|| text.galley.job.sections.first().map(|s| s.format.color))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "text {:.0},{:.0} {} {:?}",
+                text.pos.x,
+                text.pos.y,
+                hex(color),
+                text.galley.text()
+            );
+        }
+        Shape::Rect(rect) => {
+            let _ = writeln!(
+                out,
+                "rect {:.0},{:.0} {:.0}x{:.0} fill {} stroke {}",
+                rect.rect.min.x,
+                rect.rect.min.y,
+                rect.rect.width(),
+                rect.rect.height(),
+                hex(rect.fill),
+                hex(rect.stroke.color)
+            );
+        }
+        // Lines, circles and meshes are decoration the pages don't lay out with
+        _ => (),
+    }
+}
+
+fn hex(color: Color32) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}{:02x}",
+        color.r(),
+        color.g(),
+        color.b(),
+        color.a()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(page: &str) {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let now = FixedClock::parse("2024-05-01T10:00:00+00:00").unwrap();
+        let mut state = load(&fixtures.join("cache.json"), Some(now)).unwrap();
+        state.page = control::Page::parse(page).unwrap().state();
+        let actual = render(state, None, egui::Vec2::new(800.0, 480.0)).unwrap();
+
+        let expected = fixtures.join(format!("snapshots/{page}.snap"));
+        let expected = std::fs::read_to_string(&expected).unwrap_or_default();
+        if let Err(e) = compare(&expected, &actual) {
+            panic!("{page}.snap {e}\nupdate it with: console-gui snapshot fixtures/cache.json --page {page} --now 2024-05-01T10:00:00+00:00 --expect fixtures/snapshots/{page}.snap --update");
+        }
+    }
+
+    #[test]
+    fn home() {
+        check("home");
+    }
+
+    #[test]
+    fn pull_requests() {
+        check("pull_requests");
+    }
+
+    #[test]
+    fn shortcuts() {
+        check("shortcuts");
+    }
+}
@@ -2,29 +2,79 @@ pub const ZOOM_COLOR: egui::Color32 = egui::Color32::from_rgb(0x2D, 0x8C, 0xFF);
 pub const BG: egui::Color32 = egui::Color32::BLACK;
 pub const FG: egui::Color32 = egui::Color32::GRAY;
 pub const FG_MUTED: egui::Color32 = egui::Color32::from_rgb(80, 80, 80);
+pub const MERGED: egui::Color32 = egui::Color32::from_rgb(0x89, 0x57, 0xE5);
+pub const DANGER: egui::Color32 = egui::Color32::from_rgb(0xE0, 0x3C, 0x31);
 pub const STROKE: f32 = 1.0;
 
+pub fn age(seconds: i64) -> String {
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else if seconds < 60 * 60 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}h", seconds / (60 * 60))
+    }
+}
+
 fn rounding_div(a: i64, b: i64) -> i64 {
     (a as f64 / b as f64).round() as i64
 }
 
-pub fn eta(time: i64) -> String {
-    let now = chrono::prelude::Utc::now().timestamp();
-    let eta = now - time;
+// How far `time` is from `now`: "+ 5m" in the past, "- 2h" in the future. Units are
+// picked after rounding, so 59m30s reads " 1h" rather than "60m".
+pub fn eta(time: i64, now: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
 
-    let sign = if eta.signum() == 1 { "+" } else { "-" };
+    let eta = now - time;
+    let sign = match rounding_div(eta, MINUTE).signum() {
+        1 => "+",
+        -1 => "-",
+        _ => " ",
+    };
     let eta = eta.abs();
 
     // ETA > 12 hours, show at least 1 day
-    const MINUTE: i64 = 60;
-    const HOUR: i64 = 60 * MINUTE;
-    if eta > 24 * HOUR {
-        return format!("{}{:>2}d", sign, rounding_div(eta, 24 * HOUR));
-    } else if eta > 12 * HOUR {
-        return format!("{} 1d", sign);
-    } else if eta > HOUR {
-        return format!("{}{:>2}h", sign, rounding_div(eta, HOUR));
+    if rounding_div(eta, MINUTE) < 60 {
+        format!("{}{:>2}m", sign, rounding_div(eta, MINUTE))
+    } else if eta <= 12 * HOUR {
+        format!("{}{:>2}h", sign, rounding_div(eta, HOUR))
     } else {
-        return format!("{}{:>2}m", sign, rounding_div(eta, MINUTE));
+        format!("{}{:>2}d", sign, rounding_div(eta, 24 * HOUR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_boundaries() {
+        const MINUTE: i64 = 60;
+        const HOUR: i64 = 60 * MINUTE;
+        let now = 1_714_557_600;
+        let ago = |seconds: i64| eta(now - seconds, now);
+
+        assert_eq!(ago(0), "  0m");
+        assert_eq!(ago(29), "  0m");
+        assert_eq!(ago(5 * MINUTE), "+ 5m");
+        assert_eq!(ago(59 * MINUTE), "+59m");
+        assert_eq!(ago(59 * MINUTE + 29), "+59m");
+        // Rounds up to 60m, which reads as an hour
+        assert_eq!(ago(59 * MINUTE + 30), "+ 1h");
+        assert_eq!(ago(12 * HOUR), "+12h");
+        assert_eq!(ago(12 * HOUR + 1), "+ 1d");
+        assert_eq!(ago(36 * HOUR), "+ 2d");
+    }
+
+    #[test]
+    fn eta_in_the_future() {
+        let now = 1_714_557_600;
+        let ahead = |seconds: i64| eta(now + seconds, now);
+
+        assert_eq!(ahead(29), "  0m");
+        assert_eq!(ahead(15 * 60), "-15m");
+        assert_eq!(ahead(59 * 60 + 30), "- 1h");
+        assert_eq!(ahead(12 * 60 * 60 + 1), "- 1d");
     }
 }
@@ -0,0 +1,211 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use std::sync::{Arc, Mutex};
+
+use crate::config::WebhookConfig;
+use crate::credentials::{CredentialStore, Token};
+use crate::AppState;
+
+// Events are applied by refetching the account's sources, so CI runs that fire a
+// burst of check events only cause one fetch.
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Events that can change a pull request or its checks, reviews and comments. Others,
+// like ping (sent when the hook is created) or stars, are acknowledged and ignored.
+const RELEVANT_EVENTS: &[&str] = &[
+    "pull_request",
+    "pull_request_review",
+    "pull_request_review_comment",
+    "pull_request_review_thread",
+    "issue_comment",
+    "check_run",
+    "check_suite",
+    "status",
+    "push",
+];
+
+// Polling carries on as normal, so nothing is missed if the relay stops forwarding;
+// an event just pulls the next fetch forward and restarts each source's interval.
+pub async fn serve(data: Arc<Mutex<AppState>>) {
+    let webhook = match data.lock().unwrap().config.webhook.clone() {
+        Some(w) => w,
+        None => return,
+    };
+    if let Err(e) = listen(data, webhook).await {
+        eprintln!("webhook: {e}");
+    }
+}
+
+async fn listen(data: Arc<Mutex<AppState>>, webhook: WebhookConfig) -> Result<(), String> {
+    let secret = Token::webhook(&webhook)
+        .get(&CredentialStore::open().await)
+        .await
+        .ok_or("no webhook secret configured")?;
+    let key = Arc::new(ring::hmac::Key::new(
+        ring::hmac::HMAC_SHA256,
+        secret.as_bytes(),
+    ));
+    let addr: std::net::SocketAddr = webhook
+        .listen
+        .parse()
+        .map_err(|e| format!("invalid listen address {}: {e}", webhook.listen))?;
+
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let account = webhook.account.clone();
+    tokio::spawn(async move {
+        while events_rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while events_rx.try_recv().is_ok() {}
+            data.lock().unwrap().refresh_account(&account);
+        }
+    });
+
+    let make_svc = make_service_fn(move |_| {
+        let key = key.clone();
+        let events = events_tx.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(service_fn(move |req| {
+                let key = key.clone();
+                let events = events.clone();
+                async move {
+                    let status = handle(&key, req).await;
+                    if status == StatusCode::ACCEPTED {
+                        let _ = events.send(());
+                    }
+                    Response::builder().status(status).body(Body::empty())
+                }
+            }))
+        }
+    });
+
+    hyper::Server::try_bind(&addr)
+        .map_err(|e| format!("couldn't listen on {addr}: {e}"))?
+        .serve(make_svc)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn handle(key: &ring::hmac::Key, req: Request<Body>) -> StatusCode {
+    if req.method() != hyper::Method::POST {
+        return StatusCode::METHOD_NOT_ALLOWED;
+    }
+
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let event = header("X-GitHub-Event").unwrap_or_default();
+    let signature = header("X-Hub-Signature-256");
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(_) => return StatusCode::BAD_REQUEST,
+    };
+
+    // GitHub signs the raw body: X-Hub-Signature-256: sha256=<hex HMAC>
+    let signature = signature
+        .as_deref()
+        .and_then(|s| s.strip_prefix("sha256="))
+        .and_then(decode_hex);
+    match signature {
+        Some(sig) if ring::hmac::verify(key, &body, &sig).is_ok() => (),
+        _ => {
+            eprintln!("webhook: rejected {event} event with a bad signature");
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+
+    if RELEVANT_EVENTS.contains(&event.as_str()) {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::OK
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    // Digit by digit, since from_str_radix would also take a sign, e.g. "+f"
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some(digit(*hi)? << 4 | digit(*lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> ring::hmac::Key {
+        ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret")
+    }
+
+    fn sign(body: &str) -> String {
+        let tag = ring::hmac::sign(&key(), body.as_bytes());
+        let hex: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+        format!("sha256={hex}")
+    }
+
+    async fn post(event: &str, signature: Option<&str>, body: &str) -> StatusCode {
+        let mut req = Request::post("/").header("X-GitHub-Event", event);
+        if let Some(signature) = signature {
+            req = req.header("X-Hub-Signature-256", signature);
+        }
+        handle(&key(), req.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn accepts_signed_events() {
+        let body = r#"{"action":"opened"}"#;
+        assert_eq!(
+            post("pull_request", Some(&sign(body)), body).await,
+            StatusCode::ACCEPTED
+        );
+        // Signed, but nothing a refetch would pick up
+        assert_eq!(post("ping", Some(&sign(body)), body).await, StatusCode::OK);
+        assert_eq!(post("star", Some(&sign(body)), body).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let body = r#"{"action":"opened"}"#;
+        let signature = sign(body);
+        let tampered = r#"{"action":"closed"}"#;
+        assert_eq!(
+            post("pull_request", Some(&signature), tampered).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post("pull_request", None, body).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Cut short, and without the sha256= prefix
+        assert_eq!(
+            post("pull_request", Some(&signature[..20]), body).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post("pull_request", Some(&signature[7..]), body).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn only_accepts_posts() {
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(handle(&key(), req).await, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff1A"), Some(vec![0x00, 0xff, 0x1a]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+f"), None);
+    }
+}